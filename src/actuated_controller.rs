@@ -0,0 +1,213 @@
+use std::time::Duration;
+use rand::RngCore;
+use crate::detector::DetectorKind;
+use crate::intersection_manager::IntersectionManager;
+use crate::signal_controller::{Observation, SignalController, SignalRequest};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Recall {
+    Off,
+    Minimum, // Always served, even without a call
+    Maximum, // Always served, and held for max green regardless of gaps
+}
+
+#[derive(Clone, Copy)]
+pub struct ActuatedPhase {
+    pub min_green: Duration,
+    pub max_green: Duration,
+    pub passage_time: Duration, // Each actuation extends green by this much
+    pub recall: Recall,
+}
+
+impl Default for ActuatedPhase {
+    fn default() -> Self {
+        Self {
+            min_green: Duration::from_millis(500),
+            max_green: Duration::from_millis(2500),
+            passage_time: Duration::from_millis(300),
+            recall: Recall::Off,
+        }
+    }
+}
+
+// Serves the phases of the intersection's signal plan in order, skipping phases with no call.
+// Green is extended while the stop-bar loops of the phase keep seeing vehicles and ends on gap-out
+// (no actuation within the passage time) or max-out, once another phase is calling.
+#[derive(Clone)]
+pub struct ActuatedController {
+    pub phases: Vec<ActuatedPhase>,
+    current: usize,
+    calls: Vec<bool>,
+    green_start: Option<Duration>,
+    last_actuation: Duration,
+}
+
+impl ActuatedController {
+    pub fn new(phases: Vec<ActuatedPhase>) -> Self {
+        let calls = vec![false; phases.len()];
+        Self {
+            phases,
+            current: 0,
+            calls,
+            green_start: None,
+            last_actuation: Duration::ZERO,
+        }
+    }
+
+    fn next_phase(&self) -> Option<usize> {
+        (1..self.phases.len())
+            .map(|step| (self.current + step) % self.phases.len())
+            .find(|&phase| self.calls[phase] || self.phases[phase].recall != Recall::Off)
+    }
+}
+
+impl SignalController for ActuatedController {
+    fn decision_interval(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn decide(&mut self, observation: &Observation, _rng: &mut dyn RngCore) -> SignalRequest {
+        let now = observation.now;
+        let plan = observation.intersection.plan();
+
+        for detector in observation.detectors.iter().filter(|detector| detector.kind == DetectorKind::Loop && detector.reading.occupied) {
+            for phase in 0..self.phases.len() {
+                if !plan.phases[phase].serves(detector.approach) {
+                    continue;
+                }
+                if phase == self.current {
+                    self.last_actuation = now;
+                } else {
+                    self.calls[phase] = true;
+                }
+            }
+        }
+        // Push buttons call the phases that run their crosswalk's parallel through movements
+        for crosswalk in observation.intersection.crosswalks.iter().filter(|crosswalk| crosswalk.called) {
+            for phase in 0..self.phases.len() {
+                if phase != self.current && crosswalk.parallel_heads().iter().any(|&head| plan.phases[phase].serves_head(head)) {
+                    self.calls[phase] = true;
+                }
+            }
+        }
+
+        // The green timers only start once clearance is over and the phase is actually showing green
+        let showing_green = IntersectionManager::heads()
+            .filter(|&head| plan.phases[self.current].serves_head(head))
+            .all(|head| observation.intersection.head(head).is_green());
+        let green_start = match (self.green_start, showing_green) {
+            (Some(start), _) => start,
+            (None, true) => *self.green_start.insert(now),
+            (None, false) => return SignalRequest::Phase(self.current),
+        };
+
+        let settings = self.phases[self.current];
+        let green_time = now.saturating_sub(green_start);
+        let gapped_out = settings.recall != Recall::Maximum && now.saturating_sub(self.last_actuation) >= settings.passage_time;
+        let maxed_out = green_time >= settings.max_green;
+
+        if green_time >= settings.min_green && (gapped_out || maxed_out) {
+            if let Some(next) = self.next_phase() {
+                self.current = next;
+                self.calls[next] = false;
+                self.green_start = None;
+                self.last_actuation = now;
+            }
+        }
+
+        SignalRequest::Phase(self.current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::detector::Detector;
+
+    // Default layout with the stop-bar loops of the given approaches occupied
+    fn occupied(manager: &IntersectionManager, approaches: &[usize]) -> Vec<Detector> {
+        let mut detectors = Detector::default_layout(manager);
+        for detector in &mut detectors {
+            detector.reading.occupied = detector.kind == DetectorKind::Loop && approaches.contains(&detector.approach);
+        }
+        detectors
+    }
+
+    // Steps controller and manager every 100 ms from start to end ms, with the loops of approaches
+    // occupied throughout
+    fn run(controller: &mut ActuatedController, manager: &mut IntersectionManager, approaches: &[usize], start: u64, end: u64) {
+        let mut rng = StdRng::seed_from_u64(0);
+        let detectors = occupied(manager, approaches);
+        for millis in (start..=end).step_by(100) {
+            let now = Duration::from_millis(millis);
+            manager.update(now);
+            let observation = Observation {
+                now,
+                intersection: manager,
+                detectors: &detectors,
+                queues: [0; 8],
+                turn_queues: [[0; 4]; 8],
+                exit_queues: [0; 4],
+            };
+            let request = controller.decide(&observation, &mut rng);
+            assert_eq!(request, SignalRequest::Phase(controller.current));
+            manager.apply(request);
+        }
+    }
+
+    fn two_phases() -> (ActuatedController, IntersectionManager) {
+        let controller = ActuatedController::new(vec![ActuatedPhase::default(); 2]);
+        let mut manager = IntersectionManager::new();
+        manager.request_phase(0);
+        manager.update(Duration::ZERO);
+        (controller, manager)
+    }
+
+    #[test]
+    fn gaps_out_once_actuations_stop() {
+        let (mut controller, mut manager) = two_phases();
+        // East-west traffic until 1 s, north-south calling throughout
+        run(&mut controller, &mut manager, &[0, 1], 0, 1000);
+        assert_eq!(controller.current, 0);
+        run(&mut controller, &mut manager, &[1], 1100, 1200);
+        assert_eq!(controller.current, 0);
+        run(&mut controller, &mut manager, &[1], 1300, 1300);
+        assert_eq!(controller.current, 1);
+    }
+
+    #[test]
+    fn maxes_out_under_constant_actuation() {
+        let (mut controller, mut manager) = two_phases();
+        run(&mut controller, &mut manager, &[0, 1], 0, 2400);
+        assert_eq!(controller.current, 0);
+        run(&mut controller, &mut manager, &[0, 1], 2500, 2500);
+        assert_eq!(controller.current, 1);
+    }
+
+    #[test]
+    fn stays_put_without_a_call_unless_recalled() {
+        let (mut controller, mut manager) = two_phases();
+        run(&mut controller, &mut manager, &[], 0, 5000);
+        assert_eq!(controller.current, 0);
+
+        let (mut controller, mut manager) = two_phases();
+        controller.phases[1].recall = Recall::Minimum;
+        // Gaps out at min green, as nothing ever actuated
+        run(&mut controller, &mut manager, &[], 0, 400);
+        assert_eq!(controller.current, 0);
+        run(&mut controller, &mut manager, &[], 500, 500);
+        assert_eq!(controller.current, 1);
+    }
+
+    #[test]
+    fn maximum_recall_holds_green_to_max_green() {
+        let (mut controller, mut manager) = two_phases();
+        controller.phases[0].recall = Recall::Maximum;
+        run(&mut controller, &mut manager, &[1], 0, 2400);
+        assert_eq!(controller.current, 0);
+        run(&mut controller, &mut manager, &[1], 2500, 2500);
+        assert_eq!(controller.current, 1);
+    }
+}
@@ -1,15 +1,26 @@
-use crate::config::{WIDTH, HEIGHT};
+use crate::config::{FIXED_DT, MAX_FRAME_TIME};
 use winit::application::ApplicationHandler;
 use winit::event::WindowEvent;
 use winit::event_loop::ActiveEventLoop;
 use winit::window::{Window, WindowId};
 use crate::simulation::Simulation;
+use crate::signal_controller::ControllerKind;
+use crate::recorder::Recorder;
+use crate::scenario::Scenario;
+use crate::trajectory::TrajectoryLogger;
 use std::time::{Instant, Duration};
 
 #[derive(Default)]
 pub struct App {
     window: Option<Window>,
     pub simulation: Option<Simulation>,
+    pub scenario: Scenario,
+    pub seed: u64,
+    pub controller: Option<ControllerKind>, // Builds the controllers of the next simulation; the Q-learner if unset
+    pub interpolate: bool,
+    pub recorder: Option<Recorder>,
+    pub trajectory_logger: Option<TrajectoryLogger>,
+    accumulator: Duration,
     last_redraw: Option<Instant>,
     frame_count: usize,
     last_fps_check: Option<Instant>,
@@ -19,14 +30,16 @@ impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let window_attributes = Window::default_attributes()
             .with_title("Fantastic window number one!")
-            .with_inner_size(winit::dpi::LogicalSize::new(WIDTH, HEIGHT));
+            .with_inner_size(winit::dpi::LogicalSize::new(self.scenario.network.geometry.width, self.scenario.network.geometry.height));
         let window = event_loop.create_window(window_attributes).unwrap();
 
-        let simulation = Simulation::new(Some(&window));
+        let controller = self.controller.take().unwrap_or(ControllerKind::QLearning);
+        let simulation = Simulation::new(Some(&window), &self.scenario, self.seed, &controller);
 
         self.simulation = Some(simulation);
         self.window = Some(window);
         self.last_redraw = Some(Instant::now());
+        self.accumulator = Duration::ZERO;
         self.frame_count = 0;
         self.last_fps_check = Some(Instant::now());
     }
@@ -35,16 +48,42 @@ impl ApplicationHandler for App {
         match event {
             WindowEvent::CloseRequested => {
                 println!("The close button was pressed; stopping");
+                if let Some(Err(err)) = self.recorder.as_mut().map(Recorder::flush) {
+                    eprintln!("Failed to flush the metrics recording: {}", err);
+                }
+                if let Some(Err(err)) = self.trajectory_logger.as_mut().map(TrajectoryLogger::flush) {
+                    eprintln!("Failed to flush the trajectory log: {}", err);
+                }
                 event_loop.exit();
             },
             WindowEvent::RedrawRequested => {
                 if let (Some(simulation), Some(last_redraw)) = (&mut self.simulation, &mut self.last_redraw) {
                     let now = Instant::now();
-                    let dt = now.duration_since(*last_redraw);
+                    // Clamp so a stalled frame does not queue up an unbounded number of substeps
+                    let frame_time = now.duration_since(*last_redraw).min(MAX_FRAME_TIME);
                     *last_redraw = now;
 
-                    simulation.update(dt);
-                    simulation.draw(event_loop);
+                    self.accumulator += frame_time;
+                    while self.accumulator >= FIXED_DT {
+                        simulation.update(FIXED_DT);
+                        // A failed write stops the recording rather than the simulation
+                        if let Some(Err(err)) = self.recorder.as_mut().map(|recorder| recorder.sample(simulation)) {
+                            eprintln!("Stopped recording metrics: {}", err);
+                            self.recorder = None;
+                        }
+                        if let Some(Err(err)) = self.trajectory_logger.as_mut().map(|logger| logger.log(simulation)) {
+                            eprintln!("Stopped logging trajectories: {}", err);
+                            self.trajectory_logger = None;
+                        }
+                        self.accumulator -= FIXED_DT;
+                    }
+
+                    let alpha = if self.interpolate {
+                        self.accumulator.as_secs_f64() / FIXED_DT.as_secs_f64()
+                    } else {
+                        1.0
+                    };
+                    simulation.draw(event_loop, alpha);
 
                     self.frame_count += 1;
                     let elapsed = now.duration_since(self.last_fps_check.unwrap());
@@ -0,0 +1,265 @@
+use std::time::Duration;
+use rand::Rng;
+
+// Defaults for the processes' parameters when only the name is given
+pub const DEFAULT_MIN_HEADWAY: Duration = Duration::from_secs(1);
+pub const DEFAULT_PLATOON_SIZE: usize = 5;
+pub const DEFAULT_PLATOON_HEADWAY: Duration = Duration::from_millis(1500);
+
+// How the headways between vehicles arriving at one source are distributed. Rates are set by the
+// demand and its profile; the process only shapes the gaps around the mean headway.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ArrivalProcess {
+    Deterministic, // Every headway equal to the mean
+    #[default]
+    Poisson, // Negative exponential headways
+    ShiftedExponential { min_headway: Duration }, // No two vehicles closer than min_headway
+    Platooned { size: usize, headway: Duration }, // Platoons of size vehicles headway apart, arriving at random
+}
+
+impl ArrivalProcess {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "deterministic" => Some(ArrivalProcess::Deterministic),
+            "poisson" => Some(ArrivalProcess::Poisson),
+            "shifted-exponential" => Some(ArrivalProcess::ShiftedExponential { min_headway: DEFAULT_MIN_HEADWAY }),
+            "platooned" => Some(ArrivalProcess::Platooned { size: DEFAULT_PLATOON_SIZE, headway: DEFAULT_PLATOON_HEADWAY }),
+            _ => None,
+        }
+    }
+
+    // Highest flow in vehicles per hour the process can deliver: headways can only shrink to
+    // min_headway, and a platoon cannot arrive faster than its own members
+    pub fn max_flow(&self) -> f64 {
+        match *self {
+            ArrivalProcess::Deterministic | ArrivalProcess::Poisson => f64::INFINITY,
+            ArrivalProcess::ShiftedExponential { min_headway } => 3600.0 / min_headway.as_secs_f64(),
+            ArrivalProcess::Platooned { size, headway } => 3600.0 * size as f64 / (size.saturating_sub(1) as f64 * headway.as_secs_f64()),
+        }
+    }
+}
+
+// One stretch of a demand profile, with flows scaled by factor
+#[derive(Clone, PartialEq, Debug)]
+pub struct DemandPeriod {
+    pub name: String,
+    pub duration: Duration,
+    pub factor: f64,
+}
+
+// Piecewise-constant demand over simulated time, e.g. an AM peak followed by the off-peak. After the
+// last period the profile starts over; an empty profile keeps the flows as given.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct DemandProfile {
+    pub periods: Vec<DemandPeriod>,
+}
+
+impl DemandProfile {
+    pub fn new(periods: Vec<DemandPeriod>) -> Self {
+        Self { periods }
+    }
+
+    fn length(&self) -> Duration {
+        self.periods.iter().map(|period| period.duration).sum()
+    }
+
+    // The period in force at time and when it ends
+    fn period_at(&self, time: Duration) -> Option<(&DemandPeriod, Duration)> {
+        let length = self.length();
+        if length.is_zero() {
+            return None;
+        }
+        let cycle_start = time - Duration::from_nanos((time.as_nanos() % length.as_nanos()) as u64);
+        let mut end = cycle_start;
+        for period in &self.periods {
+            end += period.duration;
+            if time < end {
+                return Some((period, end));
+            }
+        }
+        unreachable!("time falls inside the profile cycle")
+    }
+
+    // Highest factor of any period; 1 without periods
+    pub fn peak_factor(&self) -> f64 {
+        self.periods.iter().map(|period| period.factor).reduce(f64::max).unwrap_or(1.0)
+    }
+
+    pub fn factor_at(&self, time: Duration) -> f64 {
+        self.period_at(time).map_or(1.0, |(period, _)| period.factor)
+    }
+
+    // When the factor next changes after time, if it ever does
+    fn next_change(&self, time: Duration) -> Option<Duration> {
+        if self.periods.iter().all(|period| period.factor == self.periods[0].factor) {
+            return None;
+        }
+        let mut time = time;
+        loop {
+            let (period, end) = self.period_at(time)?;
+            if self.factor_at(end) != period.factor {
+                return Some(end);
+            }
+            time = end;
+        }
+    }
+}
+
+// Schedules the arrivals at one source (an entrance, or an OD origin) with a base flow in vehicles
+// per hour
+#[derive(Clone, Debug)]
+pub struct ArrivalGenerator {
+    pub process: ArrivalProcess,
+    pub flow: f64,
+    pub next_arrival: Duration,
+    platoon_left: usize, // Vehicles still to come in the current platoon
+}
+
+impl ArrivalGenerator {
+    pub fn new<R: Rng + ?Sized>(process: ArrivalProcess, flow: f64, profile: &DemandProfile, rng: &mut R) -> Self {
+        let mut generator = Self {
+            process,
+            flow,
+            next_arrival: Duration::ZERO,
+            platoon_left: 0,
+        };
+        generator.next_arrival = generator.following(Duration::ZERO, profile, rng);
+        generator
+    }
+
+    // Number of vehicles due by now; each one schedules the next
+    pub fn arrivals_until<R: Rng + ?Sized>(&mut self, now: Duration, profile: &DemandProfile, rng: &mut R) -> usize {
+        let mut arrivals = 0;
+        while self.next_arrival <= now {
+            arrivals += 1;
+            self.next_arrival = self.following(self.next_arrival, profile, rng);
+        }
+        arrivals
+    }
+
+    // Arrival after one at time, drawn at the rate in force then
+    fn following<R: Rng + ?Sized>(&mut self, time: Duration, profile: &DemandProfile, rng: &mut R) -> Duration {
+        let rate = self.flow * profile.factor_at(time) / 3600.0;
+        if rate <= 0.0 {
+            // Nothing arrives until the profile raises the flow again
+            self.platoon_left = 0;
+            return match profile.next_change(time) {
+                Some(change) if self.flow > 0.0 => self.following(change, profile, rng),
+                _ => Duration::MAX,
+            };
+        }
+        // Tiny flows give headways beyond what a Duration holds: nothing more arrives
+        Duration::try_from_secs_f64(self.headway(rate, rng)).ok()
+            .and_then(|headway| time.checked_add(headway))
+            .unwrap_or(Duration::MAX)
+    }
+
+    // Seconds to the next vehicle at rate vehicles per second
+    fn headway<R: Rng + ?Sized>(&mut self, rate: f64, rng: &mut R) -> f64 {
+        let mean = 1.0 / rate;
+        match self.process {
+            ArrivalProcess::Deterministic => mean,
+            ArrivalProcess::Poisson => exponential(mean, rng),
+            ArrivalProcess::ShiftedExponential { min_headway } => {
+                let min_headway = min_headway.as_secs_f64();
+                min_headway + exponential((mean - min_headway).max(0.0), rng)
+            }
+            ArrivalProcess::Platooned { size, headway } => {
+                if self.platoon_left > 0 {
+                    self.platoon_left -= 1;
+                    return headway.as_secs_f64();
+                }
+                // Gap from the last vehicle of one platoon to the first of the next, keeping the mean rate
+                self.platoon_left = size.saturating_sub(1);
+                let platoon_span = self.platoon_left as f64 * headway.as_secs_f64();
+                exponential((size as f64 * mean - platoon_span).max(0.0), rng)
+            }
+        }
+    }
+}
+
+// Negative exponential sample with the given mean
+fn exponential<R: Rng + ?Sized>(mean: f64, rng: &mut R) -> f64 {
+    let uniform: f64 = rng.gen();
+    -mean * (1.0 - uniform).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn factor_at_follows_the_periods_and_repeats() {
+        let profile = DemandProfile::new(vec![
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(10), factor: 2.0 },
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(20), factor: 0.5 },
+        ]);
+        assert_eq!(profile.factor_at(Duration::ZERO), 2.0);
+        assert_eq!(profile.factor_at(Duration::from_secs_f64(9.9)), 2.0);
+        assert_eq!(profile.factor_at(Duration::from_secs(10)), 0.5);
+        assert_eq!(profile.factor_at(Duration::from_secs_f64(29.9)), 0.5);
+        assert_eq!(profile.factor_at(Duration::from_secs(30)), 2.0);
+        assert_eq!(profile.factor_at(Duration::from_secs(75)), 0.5);
+        assert_eq!(profile.peak_factor(), 2.0);
+
+        assert_eq!(DemandProfile::default().factor_at(Duration::from_secs(5)), 1.0);
+        assert_eq!(DemandProfile::default().peak_factor(), 1.0);
+    }
+
+    #[test]
+    fn next_change_skips_periods_with_the_same_factor() {
+        let profile = DemandProfile::new(vec![
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(10), factor: 1.0 },
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(10), factor: 1.0 },
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(10), factor: 0.0 },
+        ]);
+        assert_eq!(profile.next_change(Duration::from_secs(5)), Some(Duration::from_secs(20)));
+        assert_eq!(profile.next_change(Duration::from_secs(20)), Some(Duration::from_secs(30)));
+        assert_eq!(profile.next_change(Duration::from_secs(25)), Some(Duration::from_secs(30)));
+
+        let constant = DemandProfile::new(vec![
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(10), factor: 1.0 },
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(10), factor: 1.0 },
+        ]);
+        assert_eq!(constant.next_change(Duration::from_secs(5)), None);
+        assert_eq!(DemandProfile::default().next_change(Duration::from_secs(5)), None);
+    }
+
+    #[test]
+    fn max_flow_by_process() {
+        assert_eq!(ArrivalProcess::Poisson.max_flow(), f64::INFINITY);
+        assert_eq!(ArrivalProcess::ShiftedExponential { min_headway: Duration::from_secs(2) }.max_flow(), 1800.0);
+        assert_eq!(ArrivalProcess::Platooned { size: 5, headway: Duration::from_millis(1500) }.max_flow(), 3000.0);
+        assert_eq!(ArrivalProcess::Platooned { size: 1, headway: Duration::from_millis(1500) }.max_flow(), f64::INFINITY);
+    }
+
+    #[test]
+    fn deterministic_arrivals_keep_the_flow() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let profile = DemandProfile::default();
+        let mut generator = ArrivalGenerator::new(ArrivalProcess::Deterministic, 720.0, &profile, &mut rng);
+        // One every 5 s
+        assert_eq!(generator.arrivals_until(Duration::from_secs_f64(4.9), &profile, &mut rng), 0);
+        assert_eq!(generator.arrivals_until(Duration::from_secs(60), &profile, &mut rng), 12);
+    }
+
+    #[test]
+    fn nothing_arrives_while_the_factor_is_zero() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let profile = DemandProfile::new(vec![
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(100), factor: 0.0 },
+            DemandPeriod { name: String::new(), duration: Duration::from_secs(100), factor: 1.0 },
+        ]);
+        let generator = ArrivalGenerator::new(ArrivalProcess::Deterministic, 720.0, &profile, &mut rng);
+        assert_eq!(generator.next_arrival, Duration::from_secs(105));
+    }
+
+    #[test]
+    fn tiny_flows_never_arrive() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let generator = ArrivalGenerator::new(ArrivalProcess::Deterministic, 1e-16, &DemandProfile::default(), &mut rng);
+        assert_eq!(generator.next_arrival, Duration::MAX);
+    }
+}
@@ -0,0 +1,358 @@
+use rand::{Rng, RngCore};
+use crate::config::STOPPED_SPEED;
+
+// Distances are in pixels and times in seconds, matching Vehicle::bounds and the simulation dt
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LeaderKind {
+    Vehicle,
+    RedSignal,
+    Yield, // Giving way to opposing traffic at the stop line
+}
+
+pub struct Leader {
+    pub gap: f64,
+    pub speed: f64,
+    pub kind: LeaderKind,
+}
+
+pub trait CarFollowingModel: Send + Sync {
+    // Acceleration for the coming step; stochastic models draw from the simulation rng
+    fn acceleration(&self, speed: f64, leader: Option<&Leader>, dt: f64, rng: &mut dyn RngCore) -> f64;
+
+    fn desired_speed(&self) -> f64;
+
+    // Used to judge whether a stop line can still be stopped for, e.g. at the onset of yellow
+    fn comfortable_deceleration(&self) -> f64;
+}
+
+#[derive(Clone, Copy)]
+pub enum ModelKind {
+    Idm(IdmParams),
+    Gipps(GippsParams),
+    Krauss(KraussParams),
+    Wiedemann(WiedemannParams),
+}
+
+impl ModelKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "idm" => Some(ModelKind::Idm(IdmParams::default())),
+            "gipps" => Some(ModelKind::Gipps(GippsParams::default())),
+            "krauss" => Some(ModelKind::Krauss(KraussParams::default())),
+            "wiedemann" => Some(ModelKind::Wiedemann(WiedemannParams::default())),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn CarFollowingModel> {
+        match *self {
+            ModelKind::Idm(params) => Box::new(params),
+            ModelKind::Gipps(params) => Box::new(params),
+            ModelKind::Krauss(params) => Box::new(params),
+            ModelKind::Wiedemann(params) => Box::new(params),
+        }
+    }
+}
+
+// Discrete-time models work out the next speed; this turns it back into an acceleration
+fn acceleration_towards(speed: f64, next_speed: f64, dt: f64) -> f64 {
+    (next_speed.max(0.0) - speed) / dt
+}
+
+#[derive(Clone, Copy)]
+pub struct IdmParams {
+    pub desired_speed: f64,
+    pub max_acceleration: f64,
+    pub comfortable_deceleration: f64,
+    pub minimum_gap: f64,
+    pub time_headway: f64,
+}
+
+impl Default for IdmParams {
+    fn default() -> Self {
+        Self {
+            desired_speed: 200.0,
+            max_acceleration: 400.0,
+            comfortable_deceleration: 600.0,
+            minimum_gap: 5.0,
+            time_headway: 0.2,
+        }
+    }
+}
+
+const ACCELERATION_EXPONENT: f64 = 4.0;
+
+// Intelligent Driver Model (Treiber, Hennecke & Helbing 2000)
+impl CarFollowingModel for IdmParams {
+    fn acceleration(&self, speed: f64, leader: Option<&Leader>, _dt: f64, _rng: &mut dyn RngCore) -> f64 {
+        let free_road = 1.0 - (speed / self.desired_speed).powf(ACCELERATION_EXPONENT);
+
+        let interaction = match leader {
+            Some(leader) => {
+                let approach_rate = speed - leader.speed;
+                let desired_gap = self.minimum_gap + (speed * self.time_headway
+                    + speed * approach_rate / (2.0 * (self.max_acceleration * self.comfortable_deceleration).sqrt())).max(0.0);
+                (desired_gap / leader.gap.max(0.1)).powi(2)
+            },
+            None => 0.0,
+        };
+
+        self.max_acceleration * (free_road - interaction)
+    }
+
+    fn desired_speed(&self) -> f64 {
+        self.desired_speed
+    }
+
+    fn comfortable_deceleration(&self) -> f64 {
+        self.comfortable_deceleration
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct GippsParams {
+    pub desired_speed: f64,
+    pub max_acceleration: f64,
+    pub max_deceleration: f64,
+    pub leader_deceleration_estimate: f64,
+    pub minimum_gap: f64,
+    pub reaction_time: f64,
+}
+
+impl Default for GippsParams {
+    fn default() -> Self {
+        Self {
+            desired_speed: 200.0,
+            max_acceleration: 400.0,
+            max_deceleration: 800.0,
+            leader_deceleration_estimate: 700.0,
+            minimum_gap: 5.0,
+            reaction_time: 0.1,
+        }
+    }
+}
+
+// Gipps (1981): the slower of a free-flow and a safe-stopping speed
+impl CarFollowingModel for GippsParams {
+    fn acceleration(&self, speed: f64, leader: Option<&Leader>, dt: f64, _rng: &mut dyn RngCore) -> f64 {
+        let tau = self.reaction_time;
+        let ratio = (speed / self.desired_speed).min(1.0);
+        let free_speed = speed + 2.5 * self.max_acceleration * tau * (1.0 - ratio) * (0.025 + ratio).sqrt();
+
+        let next_speed = match leader {
+            Some(leader) => {
+                let b = self.max_deceleration;
+                let gap = leader.gap - self.minimum_gap;
+                let discriminant = b * b * tau * tau
+                    + b * (2.0 * gap - speed * tau + leader.speed * leader.speed / self.leader_deceleration_estimate);
+                let safe_speed = -b * tau + discriminant.max(0.0).sqrt();
+                free_speed.min(safe_speed)
+            },
+            None => free_speed,
+        };
+
+        acceleration_towards(speed, next_speed, dt)
+    }
+
+    fn desired_speed(&self) -> f64 {
+        self.desired_speed
+    }
+
+    fn comfortable_deceleration(&self) -> f64 {
+        self.max_deceleration / 2.0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct KraussParams {
+    pub desired_speed: f64,
+    pub max_acceleration: f64,
+    pub max_deceleration: f64,
+    pub minimum_gap: f64,
+    pub reaction_time: f64,
+    pub imperfection: f64,
+}
+
+impl Default for KraussParams {
+    fn default() -> Self {
+        Self {
+            desired_speed: 200.0,
+            max_acceleration: 400.0,
+            max_deceleration: 800.0,
+            minimum_gap: 5.0,
+            reaction_time: 0.1,
+            imperfection: 0.5,
+        }
+    }
+}
+
+// Krauss (1998) as used by SUMO: a safe speed with random dawdling scaled by the imperfection sigma
+impl CarFollowingModel for KraussParams {
+    fn acceleration(&self, speed: f64, leader: Option<&Leader>, dt: f64, rng: &mut dyn RngCore) -> f64 {
+        let mut next_speed = (speed + self.max_acceleration * dt).min(self.desired_speed);
+
+        if let Some(leader) = leader {
+            let gap = leader.gap - self.minimum_gap;
+            let mean_speed = (speed + leader.speed) / 2.0;
+            let safe_speed = leader.speed + (gap - leader.speed * self.reaction_time)
+                / (mean_speed / self.max_deceleration + self.reaction_time);
+            next_speed = next_speed.min(safe_speed);
+        }
+
+        // Drivers do not dawdle away from a red signal they are already stopping for
+        let dawdles = leader.is_none_or(|leader| leader.kind == LeaderKind::Vehicle);
+        if dawdles {
+            next_speed -= self.imperfection * self.max_acceleration * dt * rng.gen::<f64>();
+        }
+
+        acceleration_towards(speed, next_speed, dt)
+    }
+
+    fn desired_speed(&self) -> f64 {
+        self.desired_speed
+    }
+
+    fn comfortable_deceleration(&self) -> f64 {
+        self.max_deceleration / 2.0
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct WiedemannParams {
+    pub desired_speed: f64,
+    pub max_acceleration: f64,
+    pub max_deceleration: f64,
+    pub standstill_distance: f64,
+    pub following_factor: f64,
+    pub perception_distance: f64,
+    pub oscillation_acceleration: f64,
+}
+
+impl Default for WiedemannParams {
+    fn default() -> Self {
+        Self {
+            desired_speed: 200.0,
+            max_acceleration: 400.0,
+            max_deceleration: 800.0,
+            standstill_distance: 5.0,
+            following_factor: 1.5,
+            perception_distance: 60.0,
+            oscillation_acceleration: 20.0,
+        }
+    }
+}
+
+// Wiedemann vehicles close up on a stationary leader below this, slow enough to stay queued
+const CREEP_SPEED: f64 = STOPPED_SPEED / 2.0;
+
+// Wiedemann-style psycho-physical regimes: free driving, closing in, following and emergency braking
+impl CarFollowingModel for WiedemannParams {
+    fn acceleration(&self, speed: f64, leader: Option<&Leader>, _dt: f64, _rng: &mut dyn RngCore) -> f64 {
+        let free_acceleration = self.max_acceleration * (1.0 - speed / self.desired_speed);
+
+        let leader = match leader {
+            Some(leader) => leader,
+            None => return free_acceleration,
+        };
+
+        let desired_gap = self.standstill_distance + self.following_factor * speed.sqrt();
+        let closing_speed = speed - leader.speed;
+
+        if leader.gap <= desired_gap {
+            -self.max_deceleration
+        } else if leader.gap > desired_gap + self.perception_distance {
+            free_acceleration
+        } else if leader.speed == 0.0 && speed < CREEP_SPEED {
+            // Short of a stationary leader or line: creep up to the standstill distance
+            self.oscillation_acceleration
+        } else if closing_speed > 0.0 {
+            // Decelerate so the speed difference is gone by the time the desired gap is reached
+            let braking = closing_speed * closing_speed / (2.0 * (leader.gap - desired_gap));
+            -braking.min(self.max_deceleration)
+        } else if closing_speed < 0.0 {
+            free_acceleration.min(self.oscillation_acceleration)
+        } else {
+            -self.oscillation_acceleration
+        }
+    }
+
+    fn desired_speed(&self) -> f64 {
+        self.desired_speed
+    }
+
+    fn comfortable_deceleration(&self) -> f64 {
+        self.max_deceleration / 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const DT: f64 = 0.01;
+
+    fn models() -> [(&'static str, Box<dyn CarFollowingModel>); 4] {
+        [
+            ("idm", Box::new(IdmParams::default())),
+            ("gipps", Box::new(GippsParams::default())),
+            ("krauss", Box::new(KraussParams::default())),
+            ("wiedemann", Box::new(WiedemannParams::default())),
+        ]
+    }
+
+    // Drives for seconds behind a leader that keeps leader_speed, starting gap away, or on a free
+    // road without one. Returns the final speed, the smallest gap and the final gap.
+    fn drive(model: &dyn CarFollowingModel, mut speed: f64, leader: Option<(f64, f64)>, seconds: f64) -> (f64, f64, f64) {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (mut gap, leader_speed) = leader.unwrap_or((f64::INFINITY, 0.0));
+        let mut smallest = gap;
+        for _ in 0..(seconds / DT).round() as usize {
+            let leader = leader.map(|_| Leader { gap, speed: leader_speed, kind: LeaderKind::Vehicle });
+            let acceleration = model.acceleration(speed, leader.as_ref(), DT, &mut rng);
+            speed = (speed + acceleration * DT).max(0.0);
+            gap += (leader_speed - speed) * DT;
+            smallest = smallest.min(gap);
+        }
+        (speed, smallest, gap)
+    }
+
+    #[test]
+    fn free_road_speeds_up_to_the_desired_speed() {
+        for (name, model) in models() {
+            let (speed, _, _) = drive(model.as_ref(), 0.0, None, 5.0);
+            let desired = model.desired_speed();
+            assert!(speed > 0.95 * desired && speed <= desired + 1e-9, "{} ends at {}", name, speed);
+        }
+    }
+
+    #[test]
+    fn stops_behind_a_stationary_leader() {
+        for (name, model) in models() {
+            let (speed, smallest, gap) = drive(model.as_ref(), 200.0, Some((150.0, 0.0)), 5.0);
+            assert!(smallest > 0.0, "{} ran into the leader", name);
+            assert!(speed < STOPPED_SPEED, "{} still at {}", name, speed);
+            assert!(gap < 15.0, "{} stopped {} short", name, gap);
+        }
+    }
+
+    #[test]
+    fn moves_off_from_standstill_behind_a_leader_pulling_away() {
+        for (name, model) in models() {
+            let (speed, smallest, _) = drive(model.as_ref(), 0.0, Some((10.0, 100.0)), 5.0);
+            assert!(smallest > 0.0, "{} ran into the leader", name);
+            assert!((speed - 100.0).abs() < 10.0, "{} follows at {}", name, speed);
+        }
+    }
+
+    #[test]
+    fn wiedemann_creeps_up_to_the_standstill_distance() {
+        let params = WiedemannParams::default();
+        let (speed, smallest, gap) = drive(&params, 0.0, Some((40.0, 0.0)), 20.0);
+        assert!(smallest > 0.0);
+        assert!(speed < STOPPED_SPEED);
+        assert!(gap <= params.standstill_distance + 1.0, "stuck {} short", gap);
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct SimClock {
+    elapsed: Duration,
+}
+
+impl SimClock {
+    pub fn new() -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    // Only moves when the simulation is stepped, never with the wall clock
+    pub fn advance(&mut self, dt: Duration) {
+        self.elapsed += dt;
+    }
+
+    pub fn now(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn since(&self, earlier: Duration) -> Duration {
+        self.elapsed.saturating_sub(earlier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advances_by_exactly_the_step() {
+        let mut clock = SimClock::new();
+        assert_eq!(clock.now(), Duration::ZERO);
+        for _ in 0..3 {
+            clock.advance(Duration::from_millis(10));
+        }
+        assert_eq!(clock.now(), Duration::from_millis(30));
+        assert_eq!(clock.since(Duration::from_millis(5)), Duration::from_millis(25));
+        assert_eq!(clock.since(Duration::from_secs(1)), Duration::ZERO);
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct Rectangle {
     pub x: f64,
     pub y: f64,
@@ -22,7 +23,7 @@ pub fn rectangles_intersect(rect1: &Rectangle, rect2: &Rectangle) -> bool {
     let rect1_corners = get_rotated_corners(rect1);
     let rect2_corners = get_rotated_corners(rect2);
 
-    let axes = get_axes(&rect1_corners).into_iter().chain(get_axes(&rect2_corners).into_iter());
+    let axes = get_axes(&rect1_corners).into_iter().chain(get_axes(&rect2_corners));
 
     for axis in axes {
         if !overlap_on_axis(&rect1_corners, &rect2_corners, &axis) {
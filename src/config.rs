@@ -1,5 +1,11 @@
+use std::time::Duration;
+
 pub const WIDTH: u32 = 640;
 pub const HEIGHT: u32 = 480;
 pub const BOX_SIZE: i16 = 10;
 pub const DASH_LENGTH: usize = 10; // Length of each dash
 pub const GAP_LENGTH: usize = 20;
+pub const VISION_LENGTH: u32 = 100; // How far ahead a vehicle looks for a leader or a red light
+pub const STOPPED_SPEED: f64 = 5.0; // Below this a vehicle counts as queued
+pub const FIXED_DT: Duration = Duration::from_millis(10); // Physics substep shared by windowed and headless runs
+pub const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
@@ -0,0 +1,198 @@
+use std::time::Duration;
+use crate::collision::{rectangles_intersect, Rectangle};
+use crate::drawing_util::draw_rectangle;
+use crate::intersection_manager::IntersectionManager;
+use crate::network::lane_of;
+use crate::vehicle::Vehicle;
+
+// Stop-bar presence loop and queue detector placement for the default layout
+const LOOP_SETBACK: f64 = 5.0;
+const LOOP_LENGTH: u32 = 30;
+const QUEUE_LENGTH: u32 = 150;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DetectorKind {
+    Loop, // Point/presence detection: arrivals, occupancy time and time gaps
+    Area, // Queue detection over a stretch of lane: how many vehicles are in it and how many are stopped
+}
+
+// Where a detector sits on one entrance lane: its end nearest the box is setback pixels before
+// the stop line, and it reaches length pixels further back
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DetectorPlacement {
+    pub kind: DetectorKind,
+    pub entrance: u32,
+    pub setback: f64,
+    pub length: u32,
+}
+
+impl DetectorPlacement {
+    // kind on entrance where the default layout puts it
+    pub fn new(kind: DetectorKind, entrance: u32) -> Self {
+        let (setback, length) = match kind {
+            DetectorKind::Loop => (LOOP_SETBACK, LOOP_LENGTH),
+            DetectorKind::Area => (0.0, QUEUE_LENGTH),
+        };
+        Self { kind, entrance, setback, length }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct DetectorReading {
+    pub occupied: bool,
+    pub vehicles: usize,
+    pub stopped: usize,
+    pub arrivals: usize, // Vehicles that entered the area this step
+    pub count: usize, // Vehicles that have entered the area since the start of the run
+    pub mean_speed: Option<f64>,
+    pub occupied_time: Duration, // Accumulated since the start of the run
+    pub gap: Duration, // Time since the area was last occupied; zero while occupied
+}
+
+pub struct Detector {
+    pub kind: DetectorKind,
+    pub area: Rectangle,
+    pub approach: usize,
+    pub entrance: u32,
+    pub reading: DetectorReading,
+    present: Vec<usize>,
+    last_occupied: Duration,
+}
+
+impl Detector {
+    pub fn new(kind: DetectorKind, area: Rectangle, approach: usize, entrance: u32) -> Self {
+        Self {
+            kind,
+            area,
+            approach,
+            entrance,
+            reading: DetectorReading::default(),
+            present: Vec::new(),
+            last_occupied: Duration::ZERO,
+        }
+    }
+
+    pub fn placed(intersection: &IntersectionManager, placement: &DetectorPlacement) -> Self {
+        let approach = placement.entrance as usize / 2;
+        let area = intersection.stop_lights[approach].lane_area(&lane_of(placement.entrance), placement.setback, placement.length);
+        Self::new(placement.kind, area, approach, placement.entrance)
+    }
+
+    // A stop-bar loop and a queue detector on every entrance lane
+    pub fn default_layout(intersection: &IntersectionManager) -> Vec<Detector> {
+        (0..8)
+            .flat_map(|entrance| [DetectorKind::Loop, DetectorKind::Area].map(|kind| DetectorPlacement::new(kind, entrance)))
+            .map(|placement| Self::placed(intersection, &placement))
+            .collect()
+    }
+
+    pub fn update(&mut self, now: Duration, dt: Duration, vehicles: &[Vehicle]) {
+        let inside: Vec<&Vehicle> = vehicles.iter()
+            .filter(|vehicle| rectangles_intersect(&self.area, &vehicle.bounds))
+            .collect();
+
+        let reading = &mut self.reading;
+        reading.arrivals = inside.iter().filter(|vehicle| !self.present.contains(&vehicle.id)).count();
+        reading.count += reading.arrivals;
+        reading.vehicles = inside.len();
+        reading.stopped = inside.iter().filter(|vehicle| vehicle.is_stopped()).count();
+        reading.occupied = !inside.is_empty();
+        reading.mean_speed = if inside.is_empty() {
+            None
+        } else {
+            Some(inside.iter().map(|vehicle| vehicle.speed).sum::<f64>() / inside.len() as f64)
+        };
+
+        if reading.occupied {
+            reading.occupied_time += dt;
+            self.last_occupied = now;
+        }
+        reading.gap = now.saturating_sub(self.last_occupied);
+
+        self.present = inside.iter().map(|vehicle| vehicle.id).collect();
+    }
+
+    pub fn draw(&self, frame: &mut [u8], frame_width: u32, frame_height: u32) {
+        let color = if self.reading.occupied { [255, 255, 255, 255] } else { [96, 96, 96, 255] };
+        if self.kind == DetectorKind::Loop {
+            draw_rectangle(frame, frame_width, frame_height, &self.area, color, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Geometry;
+    use crate::intersection::Intersection;
+    use crate::scenario::Scenario;
+    use crate::signal_controller::ControllerKind;
+    use crate::vehicle::{TurnDirection, VehicleType};
+
+    // On eastbound entrance 1 with its centre 20 px before the line, in the middle of the default
+    // stop-bar loop
+    fn over_the_loop(id: usize, speed: f64) -> Vehicle {
+        let geometry = Geometry::default();
+        let mut vehicle = Vehicle::with_turn(id, speed, &VehicleType::default(), &geometry, 1, TurnDirection::Straight);
+        vehicle.bounds.x += vehicle.distance_to_box() - geometry.stop_line_setback - 20.0;
+        vehicle
+    }
+
+    #[test]
+    fn loop_reports_counts_speed_occupancy_and_gaps() {
+        let mut detector = Detector::placed(&IntersectionManager::new(), &DetectorPlacement::new(DetectorKind::Loop, 1));
+        let dt = Duration::from_millis(100);
+        let (first, second) = (over_the_loop(0, 10.0), over_the_loop(1, 0.0));
+
+        detector.update(Duration::ZERO, dt, std::slice::from_ref(&first));
+        let reading = &detector.reading;
+        assert!(reading.occupied);
+        assert_eq!((reading.vehicles, reading.arrivals, reading.count, reading.stopped), (1, 1, 1, 0));
+        assert_eq!(reading.mean_speed, Some(10.0));
+        assert_eq!(reading.gap, Duration::ZERO);
+
+        detector.update(Duration::from_millis(100), dt, &[first, second]);
+        let reading = &detector.reading;
+        assert_eq!((reading.vehicles, reading.arrivals, reading.count, reading.stopped), (2, 1, 2, 1));
+        assert_eq!(reading.mean_speed, Some(5.0));
+        assert_eq!(reading.occupied_time, Duration::from_millis(200));
+
+        detector.update(Duration::from_millis(200), dt, &[]);
+        detector.update(Duration::from_millis(500), dt, &[]);
+        let reading = &detector.reading;
+        assert!(!reading.occupied);
+        assert_eq!((reading.vehicles, reading.arrivals, reading.count), (0, 0, 2));
+        assert_eq!(reading.mean_speed, None);
+        assert_eq!(reading.occupied_time, Duration::from_millis(200));
+        assert_eq!(reading.gap, Duration::from_millis(400));
+
+        // Vehicles on the other lane or further back go unseen
+        let mut other_lane = over_the_loop(2, 10.0);
+        other_lane.bounds.y -= Geometry::default().lane_width;
+        let mut further_back = over_the_loop(3, 10.0);
+        further_back.bounds.x -= 40.0;
+        detector.update(Duration::from_millis(600), dt, &[other_lane, further_back]);
+        assert!(!detector.reading.occupied);
+    }
+
+    #[test]
+    fn placement_sets_where_the_detector_sits() {
+        let manager = IntersectionManager::new();
+        assert_eq!(Detector::default_layout(&manager).len(), 16);
+        let placement = DetectorPlacement { setback: 40.0, length: 10, ..DetectorPlacement::new(DetectorKind::Loop, 1) };
+        let mut detector = Detector::placed(&manager, &placement);
+        assert_eq!((detector.approach, detector.entrance), (0, 1));
+        // 20 px before the line is short of a detector starting 40 px back
+        detector.update(Duration::ZERO, Duration::from_millis(100), &[over_the_loop(0, 10.0)]);
+        assert!(!detector.reading.occupied);
+
+        let mut intersection = Intersection::new(0, &Geometry::default(), &Scenario::default(), ControllerKind::QLearning.build());
+        assert_eq!(intersection.add_detector(&placement), 16);
+        let added = &intersection.detectors[16];
+        assert_eq!((added.kind, added.area.x, added.area.width), (DetectorKind::Loop, detector.area.x, detector.area.width));
+
+        let scenario = Scenario { detectors: Some(vec![vec![placement]]), ..Scenario::default() };
+        let intersection = Intersection::new(0, &Geometry::default(), &scenario, ControllerKind::QLearning.build());
+        assert_eq!(intersection.detectors.len(), 1);
+    }
+}
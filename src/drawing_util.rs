@@ -68,18 +68,18 @@ fn is_point_in_polygon(point: (f64, f64), corners: &[(f64, f64)]) -> bool {
 
 fn draw_polygon(frame: &mut [u8], frame_width: u32, frame_height: u32, corners: &[(f64, f64)], color: [u8; 4]) {
     for i in 0..corners.len() {
-        let (x1, y1) = corners[i];
-        let (x2, y2) = corners[(i + 1) % corners.len()];
+        let start = corners[i];
+        let end = corners[(i + 1) % corners.len()];
 
-        draw_line(frame, frame_width, frame_height, x1, y1, x2, y2, color);
+        draw_line(frame, frame_width, frame_height, start, end, color);
     }
 }
 
-fn draw_line(frame: &mut [u8], frame_width: u32, frame_height: u32, x1: f64, y1: f64, x2: f64, y2: f64, color: [u8; 4]) {
-    let x1 = x1.round() as i32;
-    let y1 = y1.round() as i32;
-    let x2 = x2.round() as i32;
-    let y2 = y2.round() as i32;
+fn draw_line(frame: &mut [u8], frame_width: u32, frame_height: u32, start: (f64, f64), end: (f64, f64), color: [u8; 4]) {
+    let x1 = start.0.round() as i32;
+    let y1 = start.1.round() as i32;
+    let x2 = end.0.round() as i32;
+    let y2 = end.1.round() as i32;
 
     let dx = (x2 - x1).abs();
     let dy = (y2 - y1).abs();
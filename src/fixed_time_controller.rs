@@ -0,0 +1,153 @@
+use std::time::Duration;
+use rand::RngCore;
+use crate::signal_controller::{Observation, SignalController, SignalRequest};
+use crate::signal_plan::SignalPlan;
+
+// Webster caps the cycle here when demand approaches or exceeds capacity
+const MAX_CYCLE: Duration = Duration::from_secs(120);
+// Per lane, in veh/h of green, when a scenario does not give one
+pub const DEFAULT_SATURATION_FLOW: f64 = 1800.0;
+
+// Pre-timed control: phases of the intersection's signal plan are served in order, each for its
+// share of the cycle. Every slot starts with the intergreen that clears the previous phase.
+#[derive(Clone)]
+pub struct FixedTimeController {
+    pub cycle_length: Duration,
+    pub greens: Vec<Duration>,
+    pub offset: Duration,
+    pub intergreen: Duration,
+}
+
+impl FixedTimeController {
+    // splits are relative weights; the effective green left after lost time is shared in proportion,
+    // or equally when the splits add up to nothing (e.g. Webster without demand). There must be at
+    // least one split, as every plan has at least one phase.
+    pub fn new(cycle_length: Duration, splits: &[f64], offset: Duration, intergreen: Duration) -> Self {
+        assert!(!splits.is_empty(), "A fixed-time controller needs at least one phase");
+        let lost_time = intergreen * splits.len() as u32;
+        let effective_green = cycle_length.saturating_sub(lost_time);
+        let total: f64 = splits.iter().sum();
+        let greens = splits.iter()
+            .map(|split| if total > 0.0 && total.is_finite() { split / total } else { 1.0 / splits.len() as f64 })
+            .map(|share| effective_green.mul_f64(share))
+            .collect();
+
+        Self {
+            cycle_length,
+            greens,
+            offset,
+            intergreen,
+        }
+    }
+
+    // Webster (1958): C0 = (1.5 L + 5) / (1 - Y), with greens proportional to each phase's critical
+    // flow ratio y = q / s. critical_flows are the heaviest lane flows per phase in veh/h.
+    pub fn webster(critical_flows: &[f64], saturation_flow: f64, offset: Duration, intergreen: Duration) -> Self {
+        let ratios: Vec<f64> = critical_flows.iter().map(|flow| flow / saturation_flow).collect();
+        let total_ratio: f64 = ratios.iter().sum();
+        let lost_time = (intergreen * ratios.len() as u32).as_secs_f64();
+
+        let cycle_length = if total_ratio < 1.0 {
+            Duration::from_secs_f64((1.5 * lost_time + 5.0) / (1.0 - total_ratio)).min(MAX_CYCLE)
+        } else {
+            MAX_CYCLE
+        };
+
+        Self::new(cycle_length, &ratios, offset, intergreen)
+    }
+
+    // Webster timing for plan, taking each phase's critical flow as the heaviest entrance lane it
+    // serves. entrance_flows are per entrance lane in veh/h, as from Demand::entrance_flows.
+    pub fn webster_for_plan(plan: &SignalPlan, entrance_flows: &[f64; 8], saturation_flow: f64, offset: Duration, intergreen: Duration) -> Self {
+        let critical_flows: Vec<f64> = plan.phases.iter().map(|phase| {
+            phase.movements.iter()
+                .flat_map(|(movement, _)| movement.entrances())
+                .map(|entrance| entrance_flows[entrance as usize])
+                .fold(0.0, f64::max)
+        }).collect();
+        Self::webster(&critical_flows, saturation_flow, offset, intergreen)
+    }
+
+    // Phase whose slot covers the given time, counting the cycle from the offset
+    pub fn phase_at(&self, now: Duration) -> usize {
+        let cycle = self.cycle_length.as_secs_f64();
+        let mut in_cycle = (now.as_secs_f64() - self.offset.as_secs_f64()).rem_euclid(cycle);
+
+        for (phase, green) in self.greens.iter().enumerate() {
+            let slot = (self.intergreen + *green).as_secs_f64();
+            if in_cycle < slot {
+                return phase;
+            }
+            in_cycle -= slot;
+        }
+
+        self.greens.len() - 1
+    }
+}
+
+impl SignalController for FixedTimeController {
+    fn decision_interval(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn decide(&mut self, observation: &Observation, _rng: &mut dyn RngCore) -> SignalRequest {
+        SignalRequest::Phase(self.phase_at(observation.now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Duration, expected: f64) {
+        assert!((actual.as_secs_f64() - expected).abs() < 1e-6, "expected {} s, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn webster_cycle_and_splits() {
+        // Y = 0.25 + 0.125, L = 4 s: C0 = (1.5 * 4 + 5) / 0.625 = 17.6 s, leaving 13.6 s of green
+        let controller = FixedTimeController::webster(&[450.0, 225.0], 1800.0, Duration::ZERO, Duration::from_secs(2));
+        assert_close(controller.cycle_length, 17.6);
+        assert_close(controller.greens[0], 13.6 * 2.0 / 3.0);
+        assert_close(controller.greens[1], 13.6 / 3.0);
+    }
+
+    #[test]
+    fn webster_without_demand_splits_equally() {
+        let controller = FixedTimeController::webster(&[0.0, 0.0], 1800.0, Duration::ZERO, Duration::from_secs(2));
+        assert_close(controller.cycle_length, 11.0);
+        assert_close(controller.greens[0], 3.5);
+        assert_close(controller.greens[1], 3.5);
+    }
+
+    #[test]
+    fn webster_over_capacity_caps_the_cycle() {
+        let controller = FixedTimeController::webster(&[1200.0, 900.0], 1800.0, Duration::ZERO, Duration::from_secs(2));
+        assert_eq!(controller.cycle_length, MAX_CYCLE);
+    }
+
+    #[test]
+    fn webster_for_plan_takes_the_heaviest_lane_per_phase() {
+        // East-west serves entrances 0, 1, 4 and 5; north-south 2, 3, 6 and 7
+        let flows = [100.0, 450.0, 0.0, 50.0, 300.0, 0.0, 225.0, 0.0];
+        let controller = FixedTimeController::webster_for_plan(&SignalPlan::two_phase(), &flows, 1800.0, Duration::ZERO, Duration::from_secs(2));
+        assert_close(controller.cycle_length, 17.6);
+        assert_close(controller.greens[0], 13.6 * 2.0 / 3.0);
+    }
+
+    #[test]
+    fn phases_follow_their_slots() {
+        let controller = FixedTimeController::new(Duration::from_secs(10), &[3.0, 1.0], Duration::from_secs(1), Duration::from_secs(1));
+        // 8 s of green shared 6 s / 2 s, each slot led by 1 s of intergreen, counted from 1 s
+        assert_eq!(controller.phase_at(Duration::from_secs_f64(1.5)), 0);
+        assert_eq!(controller.phase_at(Duration::from_secs_f64(7.9)), 0);
+        assert_eq!(controller.phase_at(Duration::from_secs_f64(8.1)), 1);
+        assert_eq!(controller.phase_at(Duration::from_secs_f64(0.5)), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one phase")]
+    fn needs_at_least_one_phase() {
+        FixedTimeController::new(Duration::from_secs(10), &[], Duration::ZERO, Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::Duration;
+use crate::geometry::Geometry;
+use crate::intersection_manager::IntersectionManager;
+use crate::signal_plan::{Movement, SignalHead, APPROACHES};
+use crate::stop_light::SignalState;
+use crate::vehicle::{State, TurnDirection, Vehicle};
+
+// Left turns and U-turns give way to opposing through and right-turning traffic, going only once
+// the next opposing vehicle is at least the critical gap from the box. Opposing vehicles held at
+// their line do not count, and a left turn the showing phase protects yields to nobody. With right
+// turn on red, a right-turner that has stopped at its red line may go too, giving way in the same
+// way to traffic heading into the leg it turns onto.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GapAcceptance {
+    pub critical_gap: Duration,
+    pub right_turn_on_red: bool,
+}
+
+impl Default for GapAcceptance {
+    fn default() -> Self {
+        Self {
+            critical_gap: Duration::from_secs(1),
+            right_turn_on_red: false,
+        }
+    }
+}
+
+impl GapAcceptance {
+    pub fn applies_to(vehicle: &Vehicle) -> bool {
+        matches!(vehicle.turn, TurnDirection::Left | TurnDirection::UTurn)
+    }
+
+    // Whether vehicle may treat its red line as a yield sign
+    pub fn may_turn_on_red(&self, vehicle: &Vehicle, manager: &IntersectionManager) -> bool {
+        self.right_turn_on_red && vehicle.turn == TurnDirection::Right && vehicle.stopped_at_line && vehicle.is_approaching()
+            && manager.stop_lights[vehicle.entrance as usize / 2].state == SignalState::Red
+    }
+
+    // Whether a vehicle waiting to turn into the box of manager's intersection has to hold back
+    pub fn must_yield(&self, vehicle: &Vehicle, vehicles: &[Vehicle], manager: &IntersectionManager, geometry: &Geometry) -> bool {
+        let approach = vehicle.entrance as usize / 2;
+        if Self::applies_to(vehicle) && manager.left_turn_protected(approach) {
+            return false;
+        }
+        let exit_heading = Movement { approach, turn: vehicle.turn }.exit_heading();
+
+        vehicles.iter()
+            .filter(|other| other.node == vehicle.node && other.id != vehicle.id)
+            .filter(|other| {
+                let other_approach = other.entrance as usize / 2;
+                if vehicle.turn == TurnDirection::Right {
+                    // Anything else bound for the same leg
+                    other_approach != approach && Movement { approach: other_approach, turn: other.turn }.exit_heading() == exit_heading
+                } else {
+                    other_approach == (approach + 2) % APPROACHES && matches!(other.turn, TurnDirection::Straight | TurnDirection::Right)
+                }
+            })
+            .any(|other| self.within_gap(other, manager, geometry))
+    }
+
+    // Whether other is crossing the box or will reach it within the critical gap
+    fn within_gap(&self, other: &Vehicle, manager: &IntersectionManager, geometry: &Geometry) -> bool {
+        if !other.is_approaching() {
+            return other.state() == State::Turning;
+        }
+        let to_box = other.distance_to_box();
+        let head = SignalHead::for_movement(&Movement { approach: other.entrance as usize / 2, turn: other.turn });
+        if manager.head(head).requires_stop() && to_box > geometry.stop_line_setback && !self.may_turn_on_red(other, manager) {
+            return false;
+        }
+        // Judged as if the other vehicle came on at least at its desired speed
+        let arrival = to_box.max(0.0) / other.speed.max(other.model.desired_speed());
+        arrival < self.critical_gap.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal_plan::SignalPlan;
+    use crate::vehicle::VehicleType;
+
+    // A stationary vehicle on entrance, to_box short of the box. IDM's default desired speed of
+    // 200 px/s puts the default critical gap at 200 px.
+    fn waiting(id: usize, entrance: u32, turn: TurnDirection, to_box: f64) -> Vehicle {
+        let mut vehicle = Vehicle::with_turn(id, 0.0, &VehicleType::default(), &Geometry::default(), entrance, turn);
+        let shift = vehicle.distance_to_box() - to_box;
+        vehicle.bounds.x += shift * vehicle.direction.cos();
+        vehicle.bounds.y += shift * vehicle.direction.sin();
+        vehicle
+    }
+
+    fn showing(plan: SignalPlan, phase: usize) -> IntersectionManager {
+        let mut manager = IntersectionManager::new();
+        manager.set_plan(plan).unwrap();
+        manager.request_phase(phase);
+        manager.update(Duration::ZERO);
+        manager
+    }
+
+    #[test]
+    fn permitted_left_yields_to_opposing_traffic_inside_the_gap() {
+        let gap_acceptance = GapAcceptance::default();
+        let geometry = Geometry::default();
+        let manager = showing(SignalPlan::two_phase(), 0);
+        // Eastbound from the inside lane, against westbound traffic
+        for turn in [TurnDirection::Left, TurnDirection::UTurn] {
+            let vehicle = waiting(0, 0, turn, 0.0);
+            for opposing in [waiting(1, 5, TurnDirection::Straight, 150.0), waiting(1, 5, TurnDirection::Right, 150.0)] {
+                assert!(gap_acceptance.must_yield(&vehicle, &[opposing], &manager, &geometry));
+            }
+            assert!(!gap_acceptance.must_yield(&vehicle, &[waiting(1, 4, TurnDirection::Left, 150.0)], &manager, &geometry));
+            assert!(!gap_acceptance.must_yield(&vehicle, &[waiting(1, 5, TurnDirection::Straight, 300.0)], &manager, &geometry));
+        }
+        assert!(!GapAcceptance::applies_to(&waiting(0, 1, TurnDirection::Straight, 0.0)));
+    }
+
+    #[test]
+    fn permitted_left_goes_while_opposing_traffic_is_held_at_its_line() {
+        let gap_acceptance = GapAcceptance::default();
+        let geometry = Geometry::default();
+        let mut manager = showing(SignalPlan::two_phase(), 0);
+        manager.stop_lights[2].state = SignalState::Red;
+        let left = waiting(0, 0, TurnDirection::Left, 0.0);
+
+        assert!(!gap_acceptance.must_yield(&left, &[waiting(1, 5, TurnDirection::Straight, 150.0)], &manager, &geometry));
+        // Already past the line, it keeps coming
+        assert!(gap_acceptance.must_yield(&left, &[waiting(1, 5, TurnDirection::Straight, 5.0)], &manager, &geometry));
+    }
+
+    #[test]
+    fn protected_left_never_yields() {
+        let gap_acceptance = GapAcceptance::default();
+        let geometry = Geometry::default();
+        let left = waiting(0, 0, TurnDirection::Left, 0.0);
+        // Opposing vehicle running its red line
+        let opposing = [waiting(1, 5, TurnDirection::Straight, 5.0)];
+
+        let mut manager = showing(SignalPlan::protected_left(), 0);
+        assert!(manager.left_turn_protected(0));
+        assert!(!gap_acceptance.must_yield(&left, &opposing, &manager, &geometry));
+
+        // Permitted in the through phase
+        let permitted = showing(SignalPlan::protected_left(), 1);
+        assert!(!permitted.left_turn_protected(0));
+        assert!(gap_acceptance.must_yield(&left, &opposing, &permitted, &geometry));
+
+        // Not yet protected while the through heads of the previous phase are still clearing
+        let mut switching = showing(SignalPlan::protected_left(), 1);
+        switching.request_phase(0);
+        switching.update(Duration::from_secs(1));
+        assert!(!switching.left_turn_protected(0));
+        assert!(gap_acceptance.must_yield(&left, &opposing, &switching, &geometry));
+
+        manager.request_phase(1);
+        assert!(!manager.left_turn_protected(0));
+    }
+
+    #[test]
+    fn right_turn_on_red_only_after_stopping_at_the_line() {
+        let manager = showing(SignalPlan::two_phase(), 1);
+        let mut right = waiting(0, 1, TurnDirection::Right, 15.0);
+        let enabled = GapAcceptance { right_turn_on_red: true, ..GapAcceptance::default() };
+        assert!(!enabled.may_turn_on_red(&right, &manager));
+
+        right.stopped_at_line = true;
+        assert!(enabled.may_turn_on_red(&right, &manager));
+        assert!(!GapAcceptance::default().may_turn_on_red(&right, &manager));
+        right.turn = TurnDirection::Straight;
+        assert!(!enabled.may_turn_on_red(&right, &manager));
+        // Not on green
+        right.turn = TurnDirection::Right;
+        assert!(!enabled.may_turn_on_red(&right, &showing(SignalPlan::two_phase(), 0)));
+    }
+
+    #[test]
+    fn right_turn_on_red_gives_way_to_traffic_bound_for_the_same_leg() {
+        let gap_acceptance = GapAcceptance { right_turn_on_red: true, ..GapAcceptance::default() };
+        let geometry = Geometry::default();
+        let manager = showing(SignalPlan::two_phase(), 1);
+        // Eastbound turning right onto the south leg, with north-south green
+        let mut right = waiting(0, 1, TurnDirection::Right, 15.0);
+        right.stopped_at_line = true;
+
+        let southbound = waiting(1, 3, TurnDirection::Straight, 150.0);
+        assert!(gap_acceptance.must_yield(&right, &[southbound], &manager, &geometry));
+        let far = waiting(1, 3, TurnDirection::Straight, 300.0);
+        assert!(!gap_acceptance.must_yield(&right, &[far], &manager, &geometry));
+        // Northbound traffic heads away from the south leg
+        let northbound = waiting(1, 7, TurnDirection::Straight, 150.0);
+        assert!(!gap_acceptance.must_yield(&right, &[northbound], &manager, &geometry));
+    }
+}
@@ -0,0 +1,59 @@
+use crate::config::{HEIGHT, WIDTH};
+use crate::vehicle::Lane;
+
+// Layout of the map and of one intersection on it, in pixels.
+// Every approach has two lanes each way; traffic drives on the right.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Geometry {
+    pub width: u32,
+    pub height: u32,
+    pub center: (f64, f64), // Centre of the intersection box
+    pub lane_width: f64,
+    pub box_size: f64, // Side of the square intersection box
+    pub stop_line_setback: f64, // Distance from the stop line to the edge of the box
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            width: WIDTH,
+            height: HEIGHT,
+            center: (WIDTH as f64 / 2.0, HEIGHT as f64 / 2.0),
+            lane_width: 25.0,
+            box_size: 100.0,
+            stop_line_setback: 10.0,
+        }
+    }
+}
+
+impl Geometry {
+    pub fn center(&self) -> (f64, f64) {
+        self.center
+    }
+
+    // The same layout for an intersection elsewhere on the map
+    pub fn centred_at(&self, x: f64, y: f64) -> Self {
+        Self {
+            center: (x, y),
+            ..*self
+        }
+    }
+
+    // Distance of a lane's centre line from the road's centre line
+    pub fn lane_offset(&self, lane: &Lane) -> f64 {
+        match lane {
+            Lane::Left => self.lane_width / 2.0,
+            Lane::Right => self.lane_width * 1.5,
+        }
+    }
+
+    pub fn in_box(&self, x: f64, y: f64) -> bool {
+        let (center_x, center_y) = self.center();
+        let half = self.box_size / 2.0;
+        (x - center_x).abs() < half && (y - center_y).abs() < half
+    }
+
+    pub fn on_map(&self, x: f64, y: f64) -> bool {
+        x >= 0.0 && x <= self.width as f64 && y >= 0.0 && y <= self.height as f64
+    }
+}
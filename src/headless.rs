@@ -0,0 +1,165 @@
+use std::io;
+use std::time::Duration;
+use rayon::prelude::*;
+use crate::simulation::Simulation;
+use crate::config::FIXED_DT;
+use crate::scenario::Scenario;
+use crate::trip::TripRecord;
+use crate::metrics::PerformanceReport;
+use crate::recorder::{Recorder, RecorderConfig};
+use crate::trajectory::{TrajectoryConfig, TrajectoryLogger};
+use crate::signal_controller::{ControllerKind, SignalController};
+
+#[derive(Clone, Copy)]
+pub enum StopCondition {
+    SimulatedTime(Duration),
+    Steps(usize),
+}
+
+#[derive(Clone)]
+pub struct RunConfig {
+    pub stop: StopCondition,
+    pub dt: Duration,
+    pub seed: u64,
+    pub scenario: Scenario,
+    pub controller: ControllerKind,
+    pub record: Option<RecorderConfig>,
+    pub trajectories: Option<TrajectoryConfig>,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            stop: StopCondition::SimulatedTime(Duration::from_secs(6)),
+            dt: FIXED_DT,
+            seed: 0,
+            scenario: Scenario::default(),
+            controller: ControllerKind::QLearning,
+            record: None,
+            trajectories: None,
+        }
+    }
+}
+
+pub struct RunResult {
+    pub seed: u64,
+    pub steps: usize,
+    pub simulated_time: Duration,
+    pub average_volume: f64,
+    pub trips: Vec<TripRecord>,
+    pub performance: Vec<PerformanceReport>, // Per intersection, in node order
+    pub controllers: Vec<Box<dyn SignalController>>,
+}
+
+// Steps a windowless simulation back to back, never waiting on the wall clock
+pub fn run(config: &RunConfig) -> io::Result<RunResult> {
+    let mut simulation: Simulation = Simulation::new(None, &config.scenario, config.seed, &config.controller);
+    let mut recorder = config.record.as_ref().map(Recorder::create).transpose()?;
+    let mut trajectory_logger = config.trajectories.as_ref().map(TrajectoryLogger::create).transpose()?;
+    let mut volume_sum = 0.0;
+    let mut steps = 0;
+
+    while !is_finished(&config.stop, &simulation, steps) {
+        simulation.update(config.dt);
+        if let Some(recorder) = &mut recorder {
+            recorder.sample(&simulation)?;
+        }
+        if let Some(trajectory_logger) = &mut trajectory_logger {
+            trajectory_logger.log(&simulation)?;
+        }
+
+        let total_volume: u32 = simulation.intersections.iter()
+            .map(|intersection| intersection.manager.intersection_volume.iter().sum::<u32>())
+            .sum();
+        volume_sum += total_volume as f64;
+        steps += 1;
+    }
+
+    let average_volume = if steps > 0 {
+        volume_sum / steps as f64
+    } else {
+        0.0
+    };
+
+    if let Some(recorder) = &mut recorder {
+        recorder.flush()?;
+    }
+    if let Some(trajectory_logger) = &mut trajectory_logger {
+        trajectory_logger.flush()?;
+    }
+
+    Ok(RunResult {
+        seed: config.seed,
+        steps,
+        simulated_time: simulation.clock.now(),
+        average_volume,
+        performance: simulation.intersections.iter().map(|intersection| intersection.metrics.report(simulation.clock.now())).collect(),
+        trips: simulation.completed_trips,
+        controllers: simulation.intersections.into_iter().map(|intersection| intersection.controller).collect(),
+    })
+}
+
+// Replication i is seeded with config.seed + i (wrapping), so a batch is reproducible regardless of worker count.
+// When recording, each replication of a batch writes its own files, suffixed with its seed.
+pub fn run_batch(config: &RunConfig, replications: usize) -> io::Result<Vec<RunResult>> {
+    (0..replications as u64).into_par_iter()
+        .map(|i| {
+            let seed = config.seed.wrapping_add(i);
+            let (record, trajectories) = if replications > 1 {
+                (config.record.as_ref().map(|record| record.for_seed(seed)),
+                 config.trajectories.as_ref().map(|trajectories| trajectories.for_seed(seed)))
+            } else {
+                (config.record.clone(), config.trajectories.clone())
+            };
+            let replication = RunConfig {
+                seed,
+                record,
+                trajectories,
+                ..config.clone()
+            };
+            run(&replication)
+        })
+        .collect()
+}
+
+fn is_finished(stop: &StopCondition, simulation: &Simulation, steps: usize) -> bool {
+    match stop {
+        StopCondition::SimulatedTime(duration) => simulation.clock.now() >= *duration,
+        StopCondition::Steps(max_steps) => steps >= *max_steps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_with_the_same_seed_match() {
+        let mut config = RunConfig { seed: 7, ..RunConfig::default() };
+        config.scenario.demand.flows = Some(vec![900.0; 8]);
+        let first = run(&config).unwrap();
+        let second = run(&config).unwrap();
+        assert!(!first.trips.is_empty());
+        assert_eq!(first.trips, second.trips);
+        assert_eq!((first.steps, first.average_volume), (second.steps, second.average_volume));
+        assert_ne!(first.trips, run(&RunConfig { seed: 8, ..config }).unwrap().trips);
+    }
+
+    #[test]
+    fn batches_do_not_depend_on_the_worker_count() {
+        let mut config = RunConfig { seed: u64::MAX - 1, stop: StopCondition::Steps(300), ..RunConfig::default() };
+        config.scenario.demand.flows = Some(vec![900.0; 8]);
+        let batch = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| run_batch(&config, 4)).unwrap()
+        };
+        let serial = batch(1);
+        let parallel = batch(4);
+        assert_eq!(serial.iter().map(|result| result.seed).collect::<Vec<_>>(), vec![u64::MAX - 1, u64::MAX, 0, 1]);
+        for (serial, parallel) in serial.iter().zip(&parallel) {
+            assert_eq!(serial.seed, parallel.seed);
+            assert_eq!(serial.trips, parallel.trips);
+            assert_eq!(serial.average_volume, parallel.average_volume);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+use rand::RngCore;
+use crate::detector::{Detector, DetectorPlacement};
+use crate::geometry::Geometry;
+use crate::intersection_manager::IntersectionManager;
+use crate::metrics::IntersectionMetrics;
+use crate::scenario::Scenario;
+use crate::signal_controller::{Observation, QueueCounts, SignalController};
+use crate::vehicle::Vehicle;
+
+// One node of the network with its own signals, controller, detectors and measurements
+pub struct Intersection {
+    pub node: usize,
+    pub manager: IntersectionManager,
+    pub controller: Box<dyn SignalController>,
+    pub detectors: Vec<Detector>,
+    pub metrics: IntersectionMetrics,
+    last_decision: Duration,
+}
+
+impl Intersection {
+    pub fn new(node: usize, geometry: &Geometry, scenario: &Scenario, controller: Box<dyn SignalController>) -> Self {
+        let mut manager = IntersectionManager::with_geometry(geometry);
+        manager.set_plan(scenario.plan.clone()).expect("Scenario signal plans are validated when loaded");
+        for stop_light in manager.stop_lights.iter_mut().chain(&mut manager.left_turn_lights) {
+            stop_light.timings = scenario.timings;
+        }
+        for crosswalk in &mut manager.crosswalks {
+            crosswalk.walk = scenario.pedestrians.walk;
+            crosswalk.clearance = scenario.pedestrians.crossing_time(crosswalk.length());
+        }
+        let detectors = match &scenario.detectors {
+            Some(layouts) => layouts[node].iter().map(|placement| Detector::placed(&manager, placement)).collect(),
+            None => Detector::default_layout(&manager),
+        };
+
+        Self {
+            node,
+            manager,
+            controller,
+            detectors,
+            metrics: IntersectionMetrics::new(),
+            last_decision: Duration::ZERO,
+        }
+    }
+
+    // Returns the new detector's index in detectors
+    pub fn add_detector(&mut self, placement: &DetectorPlacement) -> usize {
+        self.detectors.push(Detector::placed(&self.manager, placement));
+        self.detectors.len() - 1
+    }
+
+    // counts are this node's
+    pub fn update(&mut self, now: Duration, dt: Duration, vehicles: &[Vehicle], counts: QueueCounts, rng: &mut dyn RngCore) {
+        for detector in &mut self.detectors {
+            detector.update(now, dt, vehicles);
+        }
+        self.manager.update(now);
+        self.metrics.sample_queues(&counts.queues);
+
+        if now.saturating_sub(self.last_decision) >= self.controller.decision_interval() {
+            let observation = Observation {
+                now,
+                intersection: &self.manager,
+                detectors: &self.detectors,
+                queues: counts.queues,
+                turn_queues: counts.turn_queues,
+                exit_queues: counts.exit_queues,
+            };
+            let request = self.controller.decide(&observation, rng);
+            self.manager.apply(request);
+            self.last_decision = now;
+        }
+    }
+}
@@ -1,42 +1,171 @@
-use crate::stop_light::StopLight;
-use std::time::Instant;
+use crate::geometry::Geometry;
+use crate::stop_light::{SignalState, StopLight};
+use crate::pedestrian::Crosswalk;
+use crate::signal_plan::{ConflictMatrix, PlanError, Protection, SignalHead, SignalPlan, APPROACHES};
+use crate::signal_controller::SignalRequest;
+use std::time::Duration;
 use ndarray::Array1;
 
 pub struct IntersectionManager {
     pub intersection_volume: [u32; 4],
-    pub stop_lights: [StopLight; 4]
+    pub stop_lights: [StopLight; 4], // Through and right-turn heads, by approach
+    pub left_turn_lights: [StopLight; 4],
+    pub crosswalks: [Crosswalk; 4], // By leg, numbered like approaches
+    pub conflicts: ConflictMatrix,
+    plan: SignalPlan,
+    current_phase: Option<usize>, // Last phase requested; None once heads are switched by approach
 }
 
 impl IntersectionManager {
 
     pub fn new() -> Self {
-        let stop_lights = [StopLight::new(0), StopLight::new(1), StopLight::new(2), StopLight::new(3)];
+        Self::with_geometry(&Geometry::default())
+    }
+
+    pub fn with_geometry(geometry: &Geometry) -> Self {
+        let stop_lights = [0, 1, 2, 3].map(|approach| StopLight::new(approach, geometry));
+        let left_turn_lights = [0, 1, 2, 3].map(|approach| StopLight::left_turn(approach, geometry));
+        let crosswalks = [0, 1, 2, 3].map(|leg| Crosswalk::new(leg, geometry));
+        let conflicts = ConflictMatrix::from_geometry(geometry);
+        let plan = SignalPlan::two_phase();
+        plan.validate(&conflicts).expect("Default signal plan should be conflict free");
+
         Self {
             intersection_volume: [0,0,0,0],
             stop_lights,
+            left_turn_lights,
+            crosswalks,
+            conflicts,
+            plan,
+            current_phase: None,
+        }
+    }
+
+    pub fn plan(&self) -> &SignalPlan {
+        &self.plan
+    }
+
+    pub fn set_plan(&mut self, plan: SignalPlan) -> Result<(), PlanError> {
+        plan.validate(&self.conflicts)?;
+        self.plan = plan;
+        self.current_phase = None;
+        Ok(())
+    }
+
+    pub fn head(&self, head: SignalHead) -> &StopLight {
+        match head {
+            SignalHead::Through(approach) => &self.stop_lights[approach],
+            SignalHead::LeftTurn(approach) => &self.left_turn_lights[approach],
+        }
+    }
+
+    fn head_mut(&mut self, head: SignalHead) -> &mut StopLight {
+        match head {
+            SignalHead::Through(approach) => &mut self.stop_lights[approach],
+            SignalHead::LeftTurn(approach) => &mut self.left_turn_lights[approach],
+        }
+    }
+
+    pub fn heads() -> impl Iterator<Item = SignalHead> {
+        (0..APPROACHES).map(SignalHead::Through).chain((0..APPROACHES).map(SignalHead::LeftTurn))
+    }
+
+    // Asks for green on every head the phase serves and red everywhere else
+    pub fn request_phase(&mut self, phase: usize) {
+        for head in Self::heads() {
+            let green = self.plan.phases[phase].serves_head(head);
+            self.head_mut(head).request(green);
+        }
+        self.current_phase = Some(phase);
+    }
+
+    // Whether approach's left turn currently runs protected: the requested phase protects it and is
+    // fully showing, with its own heads green and every other head red
+    pub fn left_turn_protected(&self, approach: usize) -> bool {
+        let Some(phase) = self.current_phase.map(|phase| &self.plan.phases[phase]) else { return false };
+        phase.left_turn_protection(approach) == Some(Protection::Protected)
+            && Self::heads().all(|head| if phase.serves_head(head) {
+                self.head(head).is_green()
+            } else {
+                self.head(head).state == SignalState::Red
+            })
+    }
+
+    pub fn update(&mut self, now: Duration) {
+        for head in Self::heads() {
+            // Green waits until every head the plan does not pair with this one is fully red
+            let blocked = Self::heads()
+                .any(|other| other != head && self.head(other).state != SignalState::Red && !self.plan.may_share_green(head, other));
+            // Whatever the controller asks, a crosswalk's interval runs to the end of its clearance
+            let held = self.crosswalks.iter()
+                .any(|crosswalk| crosswalk.is_active() && crosswalk.parallel_heads().contains(&head));
+            self.head_mut(head).update(now, blocked, held);
+        }
+        for leg in 0..APPROACHES {
+            let parallel_green = self.crosswalks[leg].parallel_heads().iter().any(|&head| self.head(head).is_green());
+            self.crosswalks[leg].update(now, parallel_green);
         }
     }
-    pub fn update_from_action(&mut self, action: usize) {
-        if action < 4 {
-            self.stop_lights[action].flip();
-            self.stop_lights[action].time_since_flip = Instant::now();
+
+    // Approach requests switch the left-turn head along with the through head
+    pub fn apply(&mut self, request: SignalRequest) {
+        match request {
+            SignalRequest::Hold => (),
+            SignalRequest::Phase(phase) => self.request_phase(phase),
+            SignalRequest::Approach { approach, green } => {
+                self.stop_lights[approach].request(green);
+                self.left_turn_lights[approach].request(green);
+                self.current_phase = None;
+            },
         }
-        // If action is 4, do nothing
     }
 
-    pub fn get_state(&self) -> Array1<f64> {
+    pub fn get_state(&self, now: Duration) -> Array1<f64> {
         Array1::from(vec![
             self.intersection_volume[0] as f64,
             self.intersection_volume[1] as f64,
             self.intersection_volume[2] as f64,
             self.intersection_volume[3] as f64,
-            self.stop_lights[0].active as u32 as f64,
-            self.stop_lights[1].active as u32 as f64,
-            self.stop_lights[2].active as u32 as f64,
-            self.stop_lights[3].active as u32 as f64,
-            self.stop_lights[0].time_since_flip.elapsed().as_secs_f64(),
+            self.stop_lights[0].is_green() as u32 as f64,
+            self.stop_lights[1].is_green() as u32 as f64,
+            self.stop_lights[2].is_green() as u32 as f64,
+            self.stop_lights[3].is_green() as u32 as f64,
+            now.saturating_sub(self.stop_lights[0].entered_at).as_secs_f64(),
         ])
     }
 }
 
+impl Default for IntersectionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pedestrian::PedestrianSignal;
 
+    #[test]
+    fn parallel_heads_stay_green_until_the_crosswalk_clears() {
+        let mut manager = IntersectionManager::new();
+        // The west leg's crosswalk runs with north-south through traffic, phase 1
+        manager.crosswalks[0].press_button();
+        manager.request_phase(1);
+        manager.update(Duration::ZERO);
+        assert!(manager.stop_lights[1].is_green() && manager.stop_lights[3].is_green());
+        assert_eq!(manager.crosswalks[0].signal, PedestrianSignal::Walk);
+
+        // Past min_green, but WALK then 2.5 s of flashing DON'T WALK still run
+        manager.request_phase(0);
+        for millis in [1000, 2000, 3400, 3500] {
+            manager.update(Duration::from_millis(millis));
+            assert!(manager.stop_lights[1].is_green(), "left green at {} ms", millis);
+            assert!(manager.stop_lights[0].requires_stop());
+        }
+        assert_eq!(manager.crosswalks[0].signal, PedestrianSignal::DontWalk);
+        manager.update(Duration::from_millis(3600));
+        assert_eq!(manager.stop_lights[1].state, SignalState::Yellow);
+        assert_eq!(manager.stop_lights[3].state, SignalState::Yellow);
+    }
+}
@@ -0,0 +1,32 @@
+pub mod app;
+pub mod clock;
+pub mod config;
+pub mod geometry;
+pub mod network;
+pub mod routing;
+pub mod arrivals;
+pub mod turning;
+pub mod gap_acceptance;
+pub mod pedestrian;
+pub mod simulation;
+pub mod vehicle;
+pub mod trip;
+pub mod metrics;
+pub mod recorder;
+pub mod trajectory;
+pub mod car_following;
+pub mod collision;
+pub mod grid;
+pub mod detector;
+pub mod drawing_util;
+pub mod stop_light;
+pub mod signal_plan;
+pub mod intersection_manager;
+pub mod intersection;
+pub mod qlearning;
+pub mod fixed_time_controller;
+pub mod signal_controller;
+pub mod actuated_controller;
+pub mod max_pressure_controller;
+pub mod headless;
+pub mod scenario;
@@ -1,104 +1,289 @@
-mod app;
-mod config;
-mod simulation;
-mod vehicle;
-mod collision;
-mod grid;
-mod drawing_util;
-mod stop_light;
-mod intersection_manager;
-mod qlearning;
-
-use crate::app::App;
+use traffic_sim::app::App;
+use traffic_sim::headless::{run_batch, RunConfig, StopCondition};
+use traffic_sim::qlearning::QLearningController;
+use traffic_sim::signal_controller::ControllerKind;
+use traffic_sim::arrivals::ArrivalProcess;
+use traffic_sim::car_following::ModelKind;
+use traffic_sim::vehicle::VehicleType;
+use traffic_sim::fixed_time_controller::{FixedTimeController, DEFAULT_SATURATION_FLOW};
+use traffic_sim::scenario;
+use traffic_sim::actuated_controller::{ActuatedController, ActuatedPhase};
+use traffic_sim::max_pressure_controller::MaxPressureController;
+use traffic_sim::recorder::{RecordFormat, RecordedMetric, Recorder, RecorderConfig};
+use traffic_sim::trajectory::{TrajectoryConfig, TrajectoryFormat, TrajectoryLogger};
 use winit::event_loop::{ControlFlow, EventLoop};
-use std::time::{Duration, Instant};
-use rayon::prelude::*;
-use crate::simulation::Simulation;
-use std::sync::{Arc, Mutex};
-
-fn run_simulation(duration: Duration, counter: Arc<Mutex<usize>>) -> (Simulation, f64) {
-    let mut simulation = Simulation::new(None);
-    let start_time = Instant::now();
-    let mut volume_sum = 0.0;
-    let mut volume_count = 0;
-    let frame_duration = Duration::from_secs_f64(1.0 / 60.0); // 60 FPS
-
-    while start_time.elapsed() < duration {
-        let frame_start_time = Instant::now();
-        let dt = Duration::from_millis(10);
-        simulation.update(dt);
-
-        let total_volume: u32 = simulation.intersection_manager.intersection_volume.iter().sum();
-        volume_sum += total_volume as f64;
-        volume_count += 1;
-
-        let elapsed = frame_start_time.elapsed();
-        if elapsed < frame_duration {
-            std::thread::sleep(frame_duration - elapsed);
+use std::any::Any;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "usage: traffic-sim [--scenario FILE] [RECORD OPTIONS] | headless [--scenario FILE] [--runs N] [--duration SECS | --steps N] [--dt MS] [--seed N] [--model idm|gipps|krauss|wiedemann] [--flow VEH_PER_HOUR] [--arrivals deterministic|poisson|shifted-exponential|platooned] [--controller qlearning|fixed|webster|actuated|max-pressure] [--cycle SECS] [RECORD OPTIONS]
+record options: [--record PATH [--record-every SECS] [--record-format csv|jsonl] [--record-metrics volume,queue,signal,reward,epsilon]] [--trajectories PATH [--trajectory-format csv|binary]]";
+
+// Recording flags are shared by windowed and headless runs and may come in any order
+#[derive(Default)]
+struct RecordArgs {
+    path: Option<String>,
+    format: Option<RecordFormat>,
+    metrics: Option<Vec<RecordedMetric>>,
+    interval: Option<Duration>,
+    trajectories: Option<String>,
+    trajectory_format: Option<TrajectoryFormat>,
+}
+
+impl RecordArgs {
+    // Returns false if the flag is not a recording flag
+    fn parse(&mut self, flag: &str, value: &str) -> Result<bool, String> {
+        match flag {
+            "--record" => self.path = Some(value.to_string()),
+            "--record-every" => self.interval = Some(positive_seconds("record interval", value)?),
+            "--record-format" => self.format = Some(RecordFormat::from_name(value).ok_or_else(|| format!("unknown record format: {}", value))?),
+            "--record-metrics" => {
+                let metrics = value.split(',')
+                    .map(|name| RecordedMetric::from_name(name).ok_or_else(|| format!("unknown metric: {}", name)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.metrics = Some(metrics);
+            },
+            "--trajectories" => self.trajectories = Some(value.to_string()),
+            "--trajectory-format" => self.trajectory_format = Some(TrajectoryFormat::from_name(value).ok_or_else(|| format!("unknown trajectory format: {}", value))?),
+            _ => return Ok(false),
         }
+        Ok(true)
     }
 
-    let average_volume = if volume_count > 0 {
-        volume_sum / volume_count as f64
-    } else {
-        0.0
-    };
+    fn build(&self) -> Result<Option<RecorderConfig>, String> {
+        let Some(path) = &self.path else {
+            return match self.format.is_some() || self.metrics.is_some() || self.interval.is_some() {
+                true => Err("recording options need --record PATH".to_string()),
+                false => Ok(None),
+            };
+        };
 
-    let mut count = counter.lock().unwrap();
-    *count += 1;
+        let mut config = RecorderConfig::new(path);
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+        if let Some(metrics) = &self.metrics {
+            config.metrics = metrics.clone();
+        }
+        if let Some(interval) = self.interval {
+            config.interval = interval;
+        }
+        Ok(Some(config))
+    }
 
-    println!("Simulations completed: {}, Average intersection volume: {:.2}", *count, average_volume);
+    fn build_trajectories(&self) -> Result<Option<TrajectoryConfig>, String> {
+        let Some(path) = &self.trajectories else {
+            return match self.trajectory_format {
+                Some(_) => Err("--trajectory-format needs --trajectories PATH".to_string()),
+                None => Ok(None),
+            };
+        };
 
-    (simulation, average_volume)
+        let mut config = TrajectoryConfig::new(path);
+        if let Some(format) = self.trajectory_format {
+            config.format = format;
+        }
+        Ok(Some(config))
+    }
 }
 
+// Parses a positive, finite number of seconds for flag
+fn positive_seconds(what: &str, value: &str) -> Result<Duration, String> {
+    value.parse().ok().filter(|seconds: &f64| seconds.is_finite() && *seconds > 0.0)
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("invalid {}: {} (must be a positive number of seconds)", what, value))
+}
+
+// The scenario file is the starting point wherever --scenario appears; other flags override it
+fn load_scenario(args: &[String]) -> Result<(RunConfig, usize), String> {
+    match args.iter().position(|arg| arg == "--scenario") {
+        Some(i) => {
+            let path = args.get(i + 1).ok_or("missing value for --scenario")?;
+            scenario::load(Path::new(path)).map_err(|err| err.to_string())
+        },
+        None => Ok((RunConfig::default(), 1)),
+    }
+}
+
+fn parse_window_args(args: &[String]) -> Result<RunConfig, String> {
+    let (mut config, _) = load_scenario(args)?;
+    let mut record = RecordArgs::default();
+    let mut args = args.iter();
 
-fn run_simulation_batch(num_simulations: usize, duration: Duration, counter: Arc<Mutex<usize>>) -> Vec<(Simulation, f64)> {
-    (0..num_simulations).into_par_iter()
-        .map(|_| run_simulation(duration, Arc::clone(&counter)))
-        .collect()
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        if flag != "--scenario" && !record.parse(flag, value)? {
+            return Err(format!("unknown option: {}", flag));
+        }
+    }
+
+    config.record = record.build()?;
+    config.trajectories = record.build_trajectories()?;
+    Ok(config)
 }
 
-fn run_multiple_simulations(num_simulations: usize, batch_size: usize, duration: Duration) -> Vec<(Simulation, f64)> {
-    let counter = Arc::new(Mutex::new(0));
-    let mut results = Vec::new();
+fn parse_headless_args(args: &[String]) -> Result<(RunConfig, usize), String> {
+    let (mut config, mut runs) = load_scenario(args)?;
+    let mut controller = None;
+    let mut cycle = Duration::from_secs(4);
+    let mut record = RecordArgs::default();
+    let mut args = args.iter();
 
-    for _ in (0..num_simulations).step_by(batch_size) {
-        let batch_results = run_simulation_batch(batch_size, duration, Arc::clone(&counter));
-        results.extend(batch_results);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag.as_str() {
+            "--scenario" => (),
+            "--runs" => runs = value.parse().map_err(|_| format!("invalid run count: {}", value))?,
+            "--duration" => config.stop = StopCondition::SimulatedTime(positive_seconds("duration", value)?),
+            "--steps" => {
+                let steps = value.parse().map_err(|_| format!("invalid step count: {}", value))?;
+                config.stop = StopCondition::Steps(steps);
+            },
+            "--dt" => {
+                let millis = value.parse().ok().filter(|millis| *millis > 0)
+                    .ok_or_else(|| format!("invalid dt: {} (must be a positive number of milliseconds)", value))?;
+                config.dt = Duration::from_millis(millis);
+            },
+            "--seed" => config.seed = value.parse().map_err(|_| format!("invalid seed: {}", value))?,
+            "--model" => {
+                let model = ModelKind::from_name(value).ok_or_else(|| format!("unknown car-following model: {}", value))?;
+                config.scenario.vehicle_types = vec![VehicleType { model, ..VehicleType::default() }];
+            },
+            // Replaces the scenario's demand with the same flow on every entrance on the map edge
+            "--flow" => {
+                let flow: f64 = value.parse().ok().filter(|flow: &f64| flow.is_finite() && *flow >= 0.0)
+                    .ok_or_else(|| format!("invalid flow: {}", value))?;
+                let entrances = config.scenario.network.boundary_entrances().len();
+                config.scenario.demand.flows = Some(vec![flow; entrances]);
+                config.scenario.demand.od = None;
+            },
+            "--arrivals" => {
+                config.scenario.demand.arrivals = ArrivalProcess::from_name(value)
+                    .ok_or_else(|| format!("unknown arrival process: {}", value))?;
+            },
+            "--controller" => controller = Some(value.clone()),
+            "--cycle" => cycle = positive_seconds("cycle length", value)?,
+            _ => if !record.parse(flag, value)? {
+                return Err(format!("unknown option: {}", flag));
+            },
+        }
     }
 
-    results
+    // Fixed-time and actuated control give every phase of the scenario's plan the same settings;
+    // Webster times the plan for the scenario's demand
+    let timings = config.scenario.timings;
+    let phases = config.scenario.plan.phases.len();
+    config.controller = match controller.as_deref() {
+        None => config.controller,
+        Some("qlearning") => ControllerKind::QLearning,
+        Some("fixed") => ControllerKind::FixedTime(FixedTimeController::new(cycle, &vec![1.0; phases], Duration::ZERO, timings.yellow + timings.all_red)),
+        Some("webster") => {
+            let flows = config.scenario.demand.entrance_flows(&config.scenario.network);
+            ControllerKind::FixedTime(FixedTimeController::webster_for_plan(&config.scenario.plan, &flows, DEFAULT_SATURATION_FLOW, Duration::ZERO, timings.yellow + timings.all_red))
+        },
+        Some("actuated") => ControllerKind::Actuated(ActuatedController::new(vec![ActuatedPhase::default(); phases])),
+        Some("max-pressure") => ControllerKind::MaxPressure(MaxPressureController::new(Duration::from_millis(500))),
+        Some(other) => return Err(format!("unknown controller: {}", other)),
+    };
+    // --flow and --arrivals may combine into more than the process can deliver
+    config.scenario.demand.check_arrival_capacity()?;
+
+    config.record = record.build()?;
+    config.trajectories = record.build_trajectories()?;
+
+    Ok((config, runs))
+}
+
+fn run_headless(args: &[String]) {
+    let (config, runs) = match parse_headless_args(args) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let results = match run_batch(&config, runs) {
+        Ok(results) => results,
+        Err(err) => {
+            eprintln!("Failed to write run output: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    println!("seed,intersection,steps,simulated_secs,average_volume,throughput,control_delay_secs,los,approach_los,pedestrians,pedestrian_delay_secs");
+    for result in results {
+        for (intersection, performance) in result.performance.iter().enumerate() {
+            let approach_los: Vec<String> = performance.approaches.iter().map(|approach| approach.level_of_service.to_string()).collect();
+            println!("{},{},{},{:.3},{:.2},{},{:.3},{},{},{},{:.3}", result.seed, intersection, result.steps, result.simulated_time.as_secs_f64(), result.average_volume,
+                performance.throughput, performance.control_delay.as_secs_f64(), performance.level_of_service, approach_los.join(""),
+                performance.pedestrians, performance.pedestrian_delay.as_secs_f64());
+        }
+    }
 }
 
 fn main() {
-    let num_simulations = 500;
-    let simulation_duration = Duration::from_secs(6);
-    let batch_size = 16; // Adjust this according to the number of cores
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match args.first().map(String::as_str) {
+        Some("headless") => return run_headless(&args[1..]),
+        Some(other) if !other.starts_with("--") => Err(format!("unknown command: {}", other)),
+        _ => parse_window_args(&args),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let recorder = config.record.as_ref().map(Recorder::create).transpose();
+    let trajectory_logger = config.trajectories.as_ref().map(TrajectoryLogger::create).transpose();
+    let (recorder, trajectory_logger) = match (recorder, trajectory_logger) {
+        (Ok(recorder), Ok(trajectory_logger)) => (recorder, trajectory_logger),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("Failed to create run output: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Other controllers are shown as configured; the Q-learner is trained first
+    let controller = match &config.controller {
+        ControllerKind::QLearning => {
+            let num_simulations = 500;
 
-    // Run multiple simulations in batches without drawing
-    let simulation_results = run_multiple_simulations(num_simulations, batch_size, simulation_duration);
+            // Run multiple simulations without drawing
+            let training = RunConfig { record: None, trajectories: None, ..config.clone() };
+            let simulation_results = run_batch(&training, num_simulations)
+                .expect("Training runs do not record, so they cannot fail on I/O");
 
-    // Extract simulations and their average volumes
-    let simulations: Vec<Simulation> = simulation_results.into_iter().map(|(sim, _)| sim).collect();
+            // Optionally, aggregate the models or select the best model; every intersection then starts from it
+            let best_controller = simulation_results.into_iter()
+                .flat_map(|result| result.controllers)
+                .filter_map(|controller| (controller as Box<dyn Any>).downcast::<QLearningController>().ok())
+                .max_by(|a, b| {
+                    a.qlearning.q_table.sum().partial_cmp(&b.qlearning.q_table.sum()).unwrap()
+                }).unwrap();
 
-    // Optionally, aggregate the models or select the best model
-    let best_simulation = simulations.into_iter().max_by(|a, b| {
-        a.qlearning.q_table.sum().partial_cmp(&b.qlearning.q_table.sum()).unwrap()
-    }).unwrap();
+            // Save the best model (pseudo-code, implement actual saving logic)
+            // save_model(&best_controller.qlearning);
 
-    // Save the best model (pseudo-code, implement actual saving logic)
-    // save_model(&best_simulation.q_learning);
+            let qlearning = best_controller.qlearning;
+            ControllerKind::Custom(Arc::new(move || Box::new(QLearningController::with_qlearning(qlearning.clone()))))
+        },
+        controller => controller.clone(),
+    };
 
-    // Initialize the window and visualize using the best model
+    // Initialize the window and visualize using the chosen controller
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
     let mut app = App::default();
-
-    // Load the best model into the app's simulation
-    if let Some(simulation) = app.simulation.as_mut() {
-        simulation.qlearning = best_simulation.qlearning;
-    }
+    app.interpolate = true;
+    app.scenario = config.scenario;
+    app.seed = config.seed;
+    app.recorder = recorder;
+    app.trajectory_logger = trajectory_logger;
+    app.controller = Some(controller);
 
     let _ = event_loop.run_app(&mut app);
 }
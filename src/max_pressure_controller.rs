@@ -0,0 +1,104 @@
+use std::time::Duration;
+use rand::RngCore;
+use crate::signal_controller::{Observation, SignalController, SignalRequest};
+use crate::vehicle::{TurnDirection, TURN_DIRECTIONS};
+
+// Max pressure (Varaiya 2013): every decision interval, serve the phase of the intersection's
+// signal plan whose movements have the largest upstream minus downstream queue
+#[derive(Clone)]
+pub struct MaxPressureController {
+    pub decision_interval: Duration,
+    current: usize,
+}
+
+impl MaxPressureController {
+    pub fn new(decision_interval: Duration) -> Self {
+        Self {
+            decision_interval,
+            current: 0,
+        }
+    }
+}
+
+impl SignalController for MaxPressureController {
+    fn decision_interval(&self) -> Duration {
+        self.decision_interval
+    }
+
+    fn decide(&mut self, observation: &Observation, _rng: &mut dyn RngCore) -> SignalRequest {
+        // A movement's pressure is the vehicles queued to make it, minus the queue on its exit leg
+        let pressures: Vec<i64> = observation.intersection.plan().phases.iter().map(|phase| {
+            phase.movements.iter().map(|(movement, _)| {
+                let upstream: usize = movement.entrances().iter()
+                    .map(|&entrance| queued_for(&observation.turn_queues[entrance as usize], movement.turn))
+                    .sum();
+                upstream as i64 - observation.exit_queues[movement.exit_heading()] as i64
+            }).sum()
+        }).collect();
+
+        // Only leave the current phase for a strictly higher pressure
+        let best = (0..pressures.len())
+            .fold(self.current, |best, phase| if pressures[phase] > pressures[best] { phase } else { best });
+        self.current = best;
+
+        SignalRequest::Phase(best)
+    }
+}
+
+// Vehicles in one entrance's queue making turn; U-turns go with the left turns whose signal they share
+fn queued_for(turn_queues: &[usize; TURN_DIRECTIONS.len()], turn: TurnDirection) -> usize {
+    TURN_DIRECTIONS.iter().zip(turn_queues)
+        .filter(|(&queued_turn, _)| queued_turn == turn || (turn == TurnDirection::Left && queued_turn == TurnDirection::UTurn))
+        .map(|(_, &count)| count)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::intersection_manager::IntersectionManager;
+
+    // Two-phase plan: 0 east-west, 1 north-south
+    fn decide(controller: &mut MaxPressureController, turn_queues: [[usize; 4]; 8], exit_queues: [usize; 4]) -> SignalRequest {
+        let manager = IntersectionManager::new();
+        let observation = Observation {
+            now: Duration::ZERO,
+            intersection: &manager,
+            detectors: &[],
+            queues: turn_queues.map(|turns| turns.iter().sum()),
+            turn_queues,
+            exit_queues,
+        };
+        controller.decide(&observation, &mut StdRng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn serves_the_phase_with_the_most_pressure() {
+        let mut controller = MaxPressureController::new(Duration::from_secs(1));
+        let mut turn_queues = [[0; 4]; 8];
+        // Three going straight on from the north, two turning left from the west
+        turn_queues[3][1] = 3;
+        turn_queues[0][0] = 2;
+        assert_eq!(decide(&mut controller, turn_queues, [0; 4]), SignalRequest::Phase(1));
+
+        // Each movement loses the queue on its exit leg; two north-south movements and one east-west
+        // one leave by the east leg
+        assert_eq!(decide(&mut controller, turn_queues, [2, 0, 0, 0]), SignalRequest::Phase(0));
+    }
+
+    #[test]
+    fn ties_keep_the_current_phase_and_u_turns_count_as_lefts() {
+        let mut controller = MaxPressureController::new(Duration::from_secs(1));
+        let mut turn_queues = [[0; 4]; 8];
+        turn_queues[2][3] = 1;
+        assert_eq!(decide(&mut controller, turn_queues, [0; 4]), SignalRequest::Phase(1));
+
+        // Right turns queue in the outside lane
+        turn_queues[1][2] = 1;
+        assert_eq!(decide(&mut controller, turn_queues, [0; 4]), SignalRequest::Phase(1));
+        turn_queues[4][1] = 1;
+        assert_eq!(decide(&mut controller, turn_queues, [0; 4]), SignalRequest::Phase(0));
+    }
+}
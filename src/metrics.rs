@@ -0,0 +1,242 @@
+use std::fmt;
+use std::time::Duration;
+use crate::signal_plan::APPROACHES;
+use crate::trip::TripRecord;
+use crate::vehicle::{TurnDirection, TURN_DIRECTIONS};
+
+// Highway Capacity Manual level of service for signalized intersections, graded on average
+// control delay per vehicle. Thresholds are applied to simulated seconds as they are.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum LevelOfService {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+}
+
+impl LevelOfService {
+    pub fn from_control_delay(delay: Duration) -> Self {
+        match delay.as_secs_f64() {
+            d if d <= 10.0 => LevelOfService::A,
+            d if d <= 20.0 => LevelOfService::B,
+            d if d <= 35.0 => LevelOfService::C,
+            d if d <= 55.0 => LevelOfService::D,
+            d if d <= 80.0 => LevelOfService::E,
+            _ => LevelOfService::F,
+        }
+    }
+}
+
+impl fmt::Display for LevelOfService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Default)]
+struct DelayTotal {
+    trips: usize,
+    delay: Duration,
+}
+
+impl DelayTotal {
+    fn add(&mut self, delay: Duration) {
+        self.trips += 1;
+        self.delay += delay;
+    }
+
+    fn average(&self) -> Duration {
+        if self.trips == 0 {
+            Duration::ZERO
+        } else {
+            self.delay / self.trips as u32
+        }
+    }
+}
+
+// Collected every step by the simulation: queue samples per approach and delay from completed trips
+#[derive(Default)]
+pub struct IntersectionMetrics {
+    queue_histograms: [Vec<u64>; APPROACHES], // queue_histograms[approach][length] = steps with that queue
+    approach_delays: [DelayTotal; APPROACHES],
+    movement_delays: [[DelayTotal; TURN_DIRECTIONS.len()]; APPROACHES],
+    pedestrian_delays: DelayTotal,
+}
+
+pub struct MovementReport {
+    pub turn: TurnDirection,
+    pub trips: usize,
+    pub control_delay: Duration,
+}
+
+pub struct ApproachReport {
+    pub approach: usize,
+    pub throughput: usize,
+    pub flow_rate: f64, // veh/h
+    pub average_queue: f64,
+    pub queue_95th: usize,
+    pub control_delay: Duration,
+    pub level_of_service: LevelOfService,
+    pub movements: Vec<MovementReport>,
+}
+
+pub struct PerformanceReport {
+    pub throughput: usize,
+    pub control_delay: Duration,
+    pub level_of_service: LevelOfService,
+    pub approaches: Vec<ApproachReport>,
+    pub pedestrians: usize, // Crossings completed
+    pub pedestrian_delay: Duration, // Average wait at the kerb
+}
+
+impl IntersectionMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // queues are per entrance, two per approach, as in Observation::queues
+    pub fn sample_queues(&mut self, queues: &[usize; 8]) {
+        for (approach, histogram) in self.queue_histograms.iter_mut().enumerate() {
+            let length = queues[approach * 2] + queues[approach * 2 + 1];
+            if histogram.len() <= length {
+                histogram.resize(length + 1, 0);
+            }
+            histogram[length] += 1;
+        }
+    }
+
+    pub fn record_trip(&mut self, trip: &TripRecord) {
+        let approach = trip.entrance as usize / 2;
+        let turn = TURN_DIRECTIONS.iter().position(|turn| *turn == trip.turn).unwrap();
+        self.approach_delays[approach].add(trip.control_delay());
+        self.movement_delays[approach][turn].add(trip.control_delay());
+    }
+
+    pub fn record_pedestrian(&mut self, delay: Duration) {
+        self.pedestrian_delays.add(delay);
+    }
+
+    pub fn report(&self, elapsed: Duration) -> PerformanceReport {
+        let hours = elapsed.as_secs_f64() / 3600.0;
+
+        let approaches: Vec<ApproachReport> = (0..APPROACHES).map(|approach| {
+            let delays = &self.approach_delays[approach];
+            let (average_queue, queue_95th) = queue_statistics(&self.queue_histograms[approach]);
+
+            ApproachReport {
+                approach,
+                throughput: delays.trips,
+                flow_rate: if hours > 0.0 { delays.trips as f64 / hours } else { 0.0 },
+                average_queue,
+                queue_95th,
+                control_delay: delays.average(),
+                level_of_service: LevelOfService::from_control_delay(delays.average()),
+                movements: TURN_DIRECTIONS.iter().enumerate().map(|(i, &turn)| MovementReport {
+                    turn,
+                    trips: self.movement_delays[approach][i].trips,
+                    control_delay: self.movement_delays[approach][i].average(),
+                }).collect(),
+            }
+        }).collect();
+
+        // Intersection delay is the volume-weighted average over approaches
+        let mut total = DelayTotal::default();
+        for delays in &self.approach_delays {
+            total.trips += delays.trips;
+            total.delay += delays.delay;
+        }
+
+        PerformanceReport {
+            throughput: total.trips,
+            control_delay: total.average(),
+            level_of_service: LevelOfService::from_control_delay(total.average()),
+            approaches,
+            pedestrians: self.pedestrian_delays.trips,
+            pedestrian_delay: self.pedestrian_delays.average(),
+        }
+    }
+}
+
+fn queue_statistics(histogram: &[u64]) -> (f64, usize) {
+    let samples: u64 = histogram.iter().sum();
+    if samples == 0 {
+        return (0.0, 0);
+    }
+
+    let total: u64 = histogram.iter().enumerate().map(|(length, count)| length as u64 * count).sum();
+    let average = total as f64 / samples as f64;
+
+    let threshold = (samples as f64 * 0.95).ceil() as u64;
+    let mut seen = 0;
+    let percentile = histogram.iter().position(|count| {
+        seen += count;
+        seen >= threshold
+    }).unwrap_or(histogram.len() - 1);
+
+    (average, percentile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_of_service_thresholds() {
+        let grades = [
+            (0.0, LevelOfService::A), (10.0, LevelOfService::A), (10.1, LevelOfService::B),
+            (20.0, LevelOfService::B), (20.1, LevelOfService::C), (35.0, LevelOfService::C),
+            (35.1, LevelOfService::D), (55.0, LevelOfService::D), (55.1, LevelOfService::E),
+            (80.0, LevelOfService::E), (80.1, LevelOfService::F),
+        ];
+        for (delay, grade) in grades {
+            assert_eq!(LevelOfService::from_control_delay(Duration::from_secs_f64(delay)), grade, "{} s", delay);
+        }
+    }
+
+    #[test]
+    fn delays_are_averaged_by_approach_and_volume() {
+        // Control delays of 10 s and 30 s on approach 0 and 60 s on approach 2
+        let left = TripRecord {
+            vehicle_id: 0,
+            node: 0,
+            entrance: 0,
+            turn: TurnDirection::Left,
+            spawned_at: Duration::ZERO,
+            entered_at: Duration::ZERO,
+            exited_at: Duration::from_secs(14),
+            stops: 0,
+            stopped_time: Duration::ZERO,
+            distance: 0.0,
+            free_flow_time: Duration::from_secs(4),
+        };
+        let mut metrics = IntersectionMetrics::new();
+        metrics.record_trip(&left);
+        metrics.record_trip(&TripRecord { entrance: 1, turn: TurnDirection::Right, exited_at: Duration::from_secs(34), ..left.clone() });
+        metrics.record_trip(&TripRecord { entrance: 4, turn: TurnDirection::Straight, exited_at: Duration::from_secs(64), ..left.clone() });
+        let report = metrics.report(Duration::from_secs(3600));
+
+        assert_eq!(report.throughput, 3);
+        assert_eq!(report.control_delay, Duration::from_secs(100) / 3);
+        assert_eq!(report.level_of_service, LevelOfService::C);
+        assert_eq!(report.approaches[0].control_delay, Duration::from_secs(20));
+        assert_eq!(report.approaches[0].level_of_service, LevelOfService::B);
+        assert_eq!(report.approaches[0].flow_rate, 2.0);
+        assert_eq!(report.approaches[0].movements[0].control_delay, Duration::from_secs(10));
+        assert_eq!(report.approaches[2].level_of_service, LevelOfService::E);
+        assert_eq!(report.approaches[1].throughput, 0);
+    }
+
+    #[test]
+    fn queue_average_and_95th_percentile() {
+        let mut metrics = IntersectionMetrics::new();
+        for length in 0..20 {
+            metrics.sample_queues(&[length, 0, 0, 0, 0, 0, 0, 0]);
+        }
+        let report = metrics.report(Duration::from_secs(1));
+        assert_eq!(report.approaches[0].average_queue, 9.5);
+        assert_eq!(report.approaches[0].queue_95th, 18);
+        assert_eq!(report.approaches[1].queue_95th, 0);
+    }
+}
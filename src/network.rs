@@ -0,0 +1,273 @@
+use crate::geometry::Geometry;
+use crate::signal_plan::{Movement, APPROACHES};
+use crate::vehicle::{Lane, TurnDirection, TURN_DIRECTIONS};
+
+// Headings are quarter turns: 0 east, 1 south, 2 west, 3 north
+const STEPS: [(i64, i64); APPROACHES] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Endpoint {
+    Node(usize),
+    Boundary, // Off the edge of the map: where vehicles enter and leave the network
+}
+
+// An intersection, at the centre of its box
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Node {
+    pub x: f64,
+    pub y: f64,
+}
+
+// A one-way road between two endpoints, travelled in a single heading
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Link {
+    pub from: Endpoint,
+    pub to: Endpoint,
+    pub heading: usize,
+    pub lanes: [Lane; 2], // Inside lane first
+}
+
+// A movement through a node from one lane of an incoming link to one lane of an outgoing link
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Connection {
+    pub node: usize,
+    pub from_link: usize,
+    pub from_lane: Lane,
+    pub to_link: usize,
+    pub to_lane: Lane,
+    pub turn: TurnDirection,
+}
+
+// A leg on the edge of the map: vehicles enter there travelling in approach's heading and leave
+// by it in the opposite heading. Origins and destinations of trips are zones.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Zone {
+    pub node: usize,
+    pub approach: usize,
+}
+
+impl Zone {
+    pub fn exit_heading(&self) -> usize {
+        (self.approach + 2) % APPROACHES
+    }
+}
+
+// Intersections on an orthogonal grid, every one with four two-lane legs. Neighbouring nodes are
+// joined by a link each way; legs on the edge of the grid run to the map boundary.
+#[derive(Clone, Debug)]
+pub struct Network {
+    pub geometry: Geometry, // Map size and the layout shared by every intersection
+    pub columns: usize,
+    pub rows: usize,
+    pub nodes: Vec<Node>,
+    pub links: Vec<Link>,
+    pub connections: Vec<Connection>,
+}
+
+impl Network {
+    // One intersection at the geometry's centre
+    pub fn single(geometry: Geometry) -> Self {
+        Self::grid(geometry, 1, 1, 0.0)
+    }
+
+    // columns x rows intersections spacing pixels apart, centred on the map; nodes are numbered row by row
+    pub fn grid(geometry: Geometry, columns: usize, rows: usize, spacing: f64) -> Self {
+        let (center_x, center_y) = geometry.center();
+        let nodes: Vec<Node> = (0..rows).flat_map(|row| (0..columns).map(move |column| Node {
+            x: center_x + (column as f64 - (columns - 1) as f64 / 2.0) * spacing,
+            y: center_y + (row as f64 - (rows - 1) as f64 / 2.0) * spacing,
+        })).collect();
+
+        let mut network = Self {
+            geometry,
+            columns,
+            rows,
+            nodes,
+            links: Vec::new(),
+            connections: Vec::new(),
+        };
+
+        // Every node gets one incoming and one outgoing link per heading
+        for node in 0..network.nodes.len() {
+            for heading in 0..APPROACHES {
+                let to = network.neighbour(node, heading).map_or(Endpoint::Boundary, Endpoint::Node);
+                network.links.push(Link { from: Endpoint::Node(node), to, heading, lanes: [Lane::Left, Lane::Right] });
+                if to == Endpoint::Boundary {
+                    network.links.push(Link { from: Endpoint::Boundary, to: Endpoint::Node(node), heading: (heading + 2) % APPROACHES, lanes: [Lane::Left, Lane::Right] });
+                }
+            }
+        }
+
+        for node in 0..network.nodes.len() {
+            for approach in 0..APPROACHES {
+                let from_link = network.link_into(node, approach);
+                for turn in TURN_DIRECTIONS {
+                    let movement = Movement { approach, turn };
+                    let to_link = network.link_out_of(node, movement.exit_heading());
+                    for entrance in movement.entrances() {
+                        let from_lane = lane_of(entrance);
+                        // Turns keep to their side of the road; through traffic keeps its lane
+                        let to_lane = match turn {
+                            TurnDirection::Left | TurnDirection::UTurn => Lane::Left,
+                            TurnDirection::Straight => from_lane,
+                            TurnDirection::Right => Lane::Right,
+                        };
+                        network.connections.push(Connection { node, from_link, from_lane, to_link, to_lane, turn });
+                    }
+                }
+            }
+        }
+
+        network
+    }
+
+    // Geometry of one node's intersection
+    pub fn node_geometry(&self, node: usize) -> Geometry {
+        self.geometry.centred_at(self.nodes[node].x, self.nodes[node].y)
+    }
+
+    fn neighbour(&self, node: usize, heading: usize) -> Option<usize> {
+        let (column, row) = ((node % self.columns) as i64, (node / self.columns) as i64);
+        let (step_x, step_y) = STEPS[heading];
+        let (column, row) = (column + step_x, row + step_y);
+        let inside = column >= 0 && row >= 0 && (column as usize) < self.columns && (row as usize) < self.rows;
+        inside.then(|| row as usize * self.columns + column as usize)
+    }
+
+    // Link arriving at node travelling in the approach's heading
+    pub fn link_into(&self, node: usize, approach: usize) -> usize {
+        self.links.iter()
+            .position(|link| link.to == Endpoint::Node(node) && link.heading == approach)
+            .expect("Every node has a link in from each approach")
+    }
+
+    pub fn link_out_of(&self, node: usize, heading: usize) -> usize {
+        self.links.iter()
+            .position(|link| link.from == Endpoint::Node(node) && link.heading == heading)
+            .expect("Every node has a link out in each heading")
+    }
+
+    // Node reached by leaving node in heading, if the link does not run off the map
+    pub fn next_node(&self, node: usize, heading: usize) -> Option<usize> {
+        match self.links[self.link_out_of(node, heading)].to {
+            Endpoint::Node(next) => Some(next),
+            Endpoint::Boundary => None,
+        }
+    }
+
+    // Node the vehicles on an approach have come from, if they entered the map there
+    pub fn previous_node(&self, node: usize, approach: usize) -> Option<usize> {
+        match self.links[self.link_into(node, approach)].from {
+            Endpoint::Node(previous) => Some(previous),
+            Endpoint::Boundary => None,
+        }
+    }
+
+    // Legs on the map boundary, by node and then approach
+    pub fn zones(&self) -> Vec<Zone> {
+        (0..self.nodes.len())
+            .flat_map(|node| (0..APPROACHES).map(move |approach| Zone { node, approach }))
+            .filter(|zone| self.previous_node(zone.node, zone.approach).is_none())
+            .collect()
+    }
+
+    // (node, entrance) pairs fed from the map boundary, where new vehicles are released
+    pub fn boundary_entrances(&self) -> Vec<(usize, u32)> {
+        self.zones().into_iter()
+            .flat_map(|zone| [(zone.node, zone.approach as u32 * 2), (zone.node, zone.approach as u32 * 2 + 1)])
+            .collect()
+    }
+
+    // Centre-line length in pixels; links to or from the boundary run to the edge of the map
+    pub fn link_length(&self, link: usize) -> f64 {
+        let link = &self.links[link];
+        let (width, height) = (self.geometry.width as f64, self.geometry.height as f64);
+        match (link.from, link.to) {
+            (Endpoint::Node(from), Endpoint::Node(to)) => {
+                let (from, to) = (self.nodes[from], self.nodes[to]);
+                (to.x - from.x).abs() + (to.y - from.y).abs()
+            }
+            (Endpoint::Node(node), Endpoint::Boundary) => {
+                let node = self.nodes[node];
+                [width - node.x, height - node.y, node.x, node.y][link.heading]
+            }
+            (Endpoint::Boundary, Endpoint::Node(node)) => {
+                let node = self.nodes[node];
+                [node.x, node.y, width - node.x, height - node.y][link.heading]
+            }
+            (Endpoint::Boundary, Endpoint::Boundary) => unreachable!("Every link has a node at one end"),
+        }
+    }
+
+    // Turns a vehicle in the given entrance lane of node can make
+    pub fn turns_from(&self, node: usize, entrance: u32) -> Vec<TurnDirection> {
+        let from_link = self.link_into(node, entrance as usize / 2);
+        let from_lane = lane_of(entrance);
+        self.connections.iter()
+            .filter(|connection| connection.node == node && connection.from_link == from_link && connection.from_lane == from_lane)
+            .map(|connection| connection.turn)
+            .collect()
+    }
+}
+
+// Even entrances are the inside lane of their approach, odd ones the outside lane
+pub fn lane_of(entrance: u32) -> Lane {
+    if entrance.is_multiple_of(2) { Lane::Left } else { Lane::Right }
+}
+
+// Entrance of the approach a vehicle travelling in heading arrives on, in the given lane
+pub fn entrance_for(heading: usize, lane: Lane) -> u32 {
+    heading as u32 * 2 + match lane {
+        Lane::Left => 0,
+        Lane::Right => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_links_and_connections() {
+        let network = Network::grid(Geometry::default(), 3, 2, 200.0);
+        assert_eq!(network.nodes.len(), 6);
+        // 7 neighbouring pairs joined both ways, and 10 legs on the edge each with a link out and in
+        let internal = network.links.iter().filter(|link| link.from != Endpoint::Boundary && link.to != Endpoint::Boundary).count();
+        assert_eq!(internal, 14);
+        assert_eq!(network.links.len(), 14 + 2 * 10);
+        assert_eq!(network.boundary_entrances().len(), 20);
+        assert_eq!(network.zones().len(), 10);
+        // Per approach: left and U-turn from the inside lane, right from the outside and through from both
+        assert_eq!(network.connections.len(), 6 * APPROACHES * 5);
+    }
+
+    #[test]
+    fn single_intersection_runs_every_leg_to_the_boundary() {
+        let network = Network::single(Geometry::default());
+        assert_eq!(network.links.len(), 8);
+        assert_eq!(network.zones().len(), 4);
+        assert!((0..APPROACHES).all(|heading| network.next_node(0, heading).is_none()));
+    }
+
+    #[test]
+    fn grid_neighbours() {
+        let network = Network::grid(Geometry::default(), 3, 2, 200.0);
+        // Node 1 is the middle of the top row
+        assert_eq!(network.next_node(1, 0), Some(2));
+        assert_eq!(network.next_node(1, 1), Some(4));
+        assert_eq!(network.next_node(1, 2), Some(0));
+        assert_eq!(network.next_node(1, 3), None);
+        // Vehicles arriving heading east come from the node to the west
+        assert_eq!(network.previous_node(1, 0), Some(0));
+        assert_eq!(network.previous_node(1, 3), Some(4));
+        assert_eq!(network.link_length(network.link_out_of(1, 0)), 200.0);
+    }
+
+    #[test]
+    fn turns_by_lane() {
+        let network = Network::single(Geometry::default());
+        assert_eq!(network.turns_from(0, 0), vec![TurnDirection::Left, TurnDirection::Straight, TurnDirection::UTurn]);
+        assert_eq!(network.turns_from(0, 1), vec![TurnDirection::Straight, TurnDirection::Right]);
+        assert_eq!(lane_of(entrance_for(3, Lane::Right)), Lane::Right);
+    }
+}
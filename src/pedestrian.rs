@@ -0,0 +1,316 @@
+use std::time::Duration;
+use crate::collision::Rectangle;
+use crate::drawing_util::draw_rectangle;
+use crate::geometry::Geometry;
+use crate::signal_plan::{SignalHead, APPROACHES};
+
+const PEDESTRIAN_SIZE: u32 = 4;
+
+// Pedestrian demand and signal timing, the same at every crosswalk. Arrivals follow the vehicle
+// demand profile, so flow is the rate at a profile factor of 1.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PedestrianSettings {
+    pub flow: f64, // Pedestrians per hour arriving at each crosswalk, split between its two ends
+    pub walking_speed: f64, // Pixels per simulated second
+    pub walk: Duration, // WALK interval; flashing DON'T WALK then lasts as long as one crossing
+}
+
+impl Default for PedestrianSettings {
+    fn default() -> Self {
+        Self {
+            flow: 0.0,
+            walking_speed: 40.0,
+            walk: Duration::from_secs(1),
+        }
+    }
+}
+
+impl PedestrianSettings {
+    // Time to walk a crosswalk of the given length, the flashing DON'T WALK clearance
+    pub fn crossing_time(&self, length: f64) -> Duration {
+        Duration::from_secs_f64(length / self.walking_speed)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PedestrianSignal {
+    Walk,
+    FlashingDontWalk, // Nobody may start crossing; those already on the crosswalk finish
+    DontWalk,
+}
+
+// Crossing of one leg, between its stop lines and the box. A push button call is served the next
+// time a through head parallel to the crosswalk is green, once per green; later calls wait for
+// the one after. The intersection keeps the parallel heads green until WALK and flashing DON'T
+// WALK are over.
+pub struct Crosswalk {
+    pub leg: usize, // Numbered like approaches: the leg that approach's vehicles arrive on
+    pub area: Rectangle,
+    pub signal: PedestrianSignal,
+    pub called: bool, // Button pressed and not yet served
+    pub entered_at: Duration,
+    pub walk: Duration,
+    pub clearance: Duration,
+    length: f64,
+    served: bool, // WALK already shown during the current parallel green
+}
+
+impl Crosswalk {
+    pub fn new(leg: usize, geometry: &Geometry) -> Self {
+        let (center_x, center_y) = geometry.center();
+        let heading = leg as f64 * std::f64::consts::PI / 2.0;
+        let distance = (geometry.box_size + geometry.stop_line_setback) / 2.0;
+        let length = geometry.lane_width * 4.0;
+        let (along, across) = (geometry.stop_line_setback.round() as u32, length.round() as u32);
+        let (width, height) = if leg.is_multiple_of(2) { (along, across) } else { (across, along) };
+        let settings = PedestrianSettings::default();
+
+        Self {
+            leg,
+            area: Rectangle::new(center_x - distance * heading.cos(), center_y - distance * heading.sin(), width, height, 0.0),
+            signal: PedestrianSignal::DontWalk,
+            called: false,
+            entered_at: Duration::ZERO,
+            walk: settings.walk,
+            clearance: settings.crossing_time(length),
+            length,
+            served: false,
+        }
+    }
+
+    pub fn length(&self) -> f64 {
+        self.length
+    }
+
+    // Through heads of the cross street, whose green the crosswalk runs with
+    pub fn parallel_heads(&self) -> [SignalHead; 2] {
+        [SignalHead::Through((self.leg + 1) % APPROACHES), SignalHead::Through((self.leg + 3) % APPROACHES)]
+    }
+
+    pub fn press_button(&mut self) {
+        if self.signal != PedestrianSignal::Walk {
+            self.called = true;
+        }
+    }
+
+    // parallel_green is true while one of the parallel heads shows green; the intervals end early
+    // if it goes out
+    pub fn update(&mut self, now: Duration, parallel_green: bool) {
+        let in_state = now.saturating_sub(self.entered_at);
+        if !parallel_green {
+            self.served = false;
+        }
+
+        match self.signal {
+            PedestrianSignal::DontWalk => {
+                if self.called && parallel_green && !self.served {
+                    self.called = false;
+                    self.served = true;
+                    self.enter(PedestrianSignal::Walk, now);
+                }
+            },
+            PedestrianSignal::Walk => {
+                if !parallel_green {
+                    self.enter(PedestrianSignal::DontWalk, now);
+                } else if in_state >= self.walk {
+                    self.enter(PedestrianSignal::FlashingDontWalk, now);
+                }
+            },
+            PedestrianSignal::FlashingDontWalk => {
+                if !parallel_green || in_state >= self.clearance {
+                    self.enter(PedestrianSignal::DontWalk, now);
+                }
+            },
+        }
+    }
+
+    // Whether the interval pedestrians were given is still running
+    pub fn is_active(&self) -> bool {
+        self.signal != PedestrianSignal::DontWalk
+    }
+
+    fn enter(&mut self, signal: PedestrianSignal, now: Duration) {
+        self.signal = signal;
+        self.entered_at = now;
+    }
+
+    // Point offset along the crosswalk from its centre, towards the driver's right of the approach
+    pub fn point(&self, offset: f64) -> (f64, f64) {
+        let heading = self.leg as f64 * std::f64::consts::PI / 2.0;
+        (self.area.x - offset * heading.sin(), self.area.y + offset * heading.cos())
+    }
+
+    pub fn draw(&self, frame: &mut [u8], frame_width: u32, frame_height: u32) {
+        let color = match self.signal {
+            PedestrianSignal::Walk => [255, 255, 255, 255],
+            PedestrianSignal::FlashingDontWalk => [255, 160, 0, 255],
+            PedestrianSignal::DontWalk => [120, 120, 120, 255],
+        };
+        draw_rectangle(frame, frame_width, frame_height, &self.area, color, false);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PedestrianState {
+    Waiting, // At the kerb for WALK
+    Crossing,
+}
+
+pub struct Pedestrian {
+    pub id: usize,
+    pub node: usize,
+    pub leg: usize,
+    pub state: PedestrianState,
+    pub walked: f64, // Distance covered on the crosswalk
+    pub from_right: bool, // Starts on the driver's right of the leg's approach
+    pub arrived_at: Duration,
+    pub started_at: Option<Duration>,
+    pub walking_speed: f64,
+}
+
+impl Pedestrian {
+    pub fn new(id: usize, node: usize, leg: usize, from_right: bool, walking_speed: f64, arrived_at: Duration) -> Self {
+        Self {
+            id,
+            node,
+            leg,
+            state: PedestrianState::Waiting,
+            walked: 0.0,
+            from_right,
+            arrived_at,
+            started_at: None,
+            walking_speed,
+        }
+    }
+
+    pub fn is_crossing(&self) -> bool {
+        self.state == PedestrianState::Crossing
+    }
+
+    // Steps off on WALK and keeps going once on the crosswalk, whatever the signal does
+    pub fn update(&mut self, now: Duration, dt: Duration, crosswalk: &Crosswalk) {
+        match self.state {
+            PedestrianState::Waiting => {
+                if crosswalk.signal == PedestrianSignal::Walk {
+                    self.state = PedestrianState::Crossing;
+                    self.started_at = Some(now);
+                }
+            },
+            PedestrianState::Crossing => self.walked += self.walking_speed * dt.as_secs_f64(),
+        }
+    }
+
+    pub fn has_crossed(&self, crosswalk: &Crosswalk) -> bool {
+        self.walked >= crosswalk.length()
+    }
+
+    // Time spent waiting at the kerb; crossing itself is the pedestrian's free-flow time
+    pub fn delay(&self) -> Duration {
+        self.started_at.map_or(Duration::ZERO, |started| started.saturating_sub(self.arrived_at))
+    }
+
+    pub fn draw(&self, crosswalk: &Crosswalk, frame: &mut [u8], frame_width: u32, frame_height: u32) {
+        let from_centre = self.walked - crosswalk.length() / 2.0;
+        let offset = if self.from_right { -from_centre } else { from_centre };
+        let (x, y) = crosswalk.point(offset);
+        let body = Rectangle::new(x, y, PEDESTRIAN_SIZE, PEDESTRIAN_SIZE, 0.0);
+        draw_rectangle(frame, frame_width, frame_height, &body, [255, 255, 0, 255], true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crosswalk_runs_walk_then_flashing_dont_walk() {
+        // Default geometry: a 100 px crosswalk cleared in 2.5 s after 1 s of WALK
+        let mut crosswalk = Crosswalk::new(0, &Geometry::default());
+        assert_eq!(crosswalk.clearance, Duration::from_millis(2500));
+        crosswalk.update(Duration::ZERO, true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::DontWalk);
+
+        crosswalk.press_button();
+        crosswalk.update(Duration::from_secs(1), false);
+        assert_eq!(crosswalk.signal, PedestrianSignal::DontWalk);
+        crosswalk.update(Duration::from_secs(2), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::Walk);
+        assert!(!crosswalk.called);
+        crosswalk.update(Duration::from_secs_f64(2.9), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::Walk);
+        crosswalk.update(Duration::from_secs(3), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::FlashingDontWalk);
+        assert!(crosswalk.is_active());
+        crosswalk.update(Duration::from_secs_f64(5.4), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::FlashingDontWalk);
+        crosswalk.update(Duration::from_millis(5500), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::DontWalk);
+        assert!(!crosswalk.is_active());
+    }
+
+    #[test]
+    fn button_calls_are_served_once_per_green() {
+        let mut crosswalk = Crosswalk::new(0, &Geometry::default());
+        crosswalk.press_button();
+        crosswalk.update(Duration::ZERO, true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::Walk);
+        // Already walking: nothing to call
+        crosswalk.press_button();
+        assert!(!crosswalk.called);
+
+        crosswalk.update(Duration::from_secs(1), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::FlashingDontWalk);
+        crosswalk.press_button();
+        assert!(crosswalk.called);
+        crosswalk.update(Duration::from_millis(3500), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::DontWalk);
+        // Still the same green
+        crosswalk.update(Duration::from_secs(4), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::DontWalk);
+        assert!(crosswalk.called);
+
+        crosswalk.update(Duration::from_secs(5), false);
+        crosswalk.update(Duration::from_secs(6), true);
+        assert_eq!(crosswalk.signal, PedestrianSignal::Walk);
+        assert!(!crosswalk.called);
+    }
+
+    #[test]
+    fn walk_ends_when_the_parallel_heads_leave_green() {
+        let mut crosswalk = Crosswalk::new(1, &Geometry::default());
+        assert_eq!(crosswalk.parallel_heads(), [SignalHead::Through(2), SignalHead::Through(0)]);
+        crosswalk.press_button();
+        crosswalk.update(Duration::ZERO, true);
+        crosswalk.update(Duration::from_millis(500), false);
+        assert_eq!(crosswalk.signal, PedestrianSignal::DontWalk);
+    }
+
+    #[test]
+    fn pedestrian_waits_for_walk_then_crosses() {
+        let mut crosswalk = Crosswalk::new(0, &Geometry::default());
+        let mut pedestrian = Pedestrian::new(0, 0, 0, true, 40.0, Duration::from_secs(1));
+        let dt = Duration::from_secs(1);
+
+        pedestrian.update(Duration::from_secs(2), dt, &crosswalk);
+        assert_eq!(pedestrian.state, PedestrianState::Waiting);
+        assert_eq!(pedestrian.delay(), Duration::ZERO);
+
+        crosswalk.press_button();
+        crosswalk.update(Duration::from_secs(4), true);
+        pedestrian.update(Duration::from_secs(4), dt, &crosswalk);
+        assert!(pedestrian.is_crossing());
+        assert_eq!(pedestrian.walked, 0.0);
+        assert_eq!(pedestrian.delay(), Duration::from_secs(3));
+
+        // Keeps going whatever the signal shows
+        crosswalk.update(Duration::from_secs(5), false);
+        for now in 5..7 {
+            pedestrian.update(Duration::from_secs(now), dt, &crosswalk);
+        }
+        assert_eq!(pedestrian.walked, 80.0);
+        assert!(!pedestrian.has_crossed(&crosswalk));
+        pedestrian.update(Duration::from_secs(7), dt, &crosswalk);
+        assert!(pedestrian.has_crossed(&crosswalk));
+        assert_eq!(pedestrian.delay(), Duration::from_secs(3));
+    }
+}
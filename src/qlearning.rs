@@ -1,6 +1,9 @@
 use ndarray::{Array1, Array2};
-use rand::Rng;
+use rand::{Rng, RngCore};
+use std::time::Duration;
+use crate::signal_controller::{Observation, SignalController, SignalRequest};
 
+#[derive(Clone)]
 pub struct QLearning {
     pub q_table: Array2<f64>,
     learning_rate: f64,
@@ -20,8 +23,7 @@ impl QLearning {
         }
     }
 
-    pub fn choose_action(&self, state: &Array1<f64>) -> usize {
-        let mut rng = rand::thread_rng();
+    pub fn choose_action<R: Rng + ?Sized>(&self, state: &Array1<f64>, rng: &mut R) -> usize {
         if rng.gen::<f64>() < self.epsilon {
             rng.gen_range(0..self.q_table.shape()[1])
         } else {
@@ -35,6 +37,10 @@ impl QLearning {
         }
     }
 
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
     pub fn update(&mut self, state: &Array1<f64>, action: usize, reward: f64, next_state: &Array1<f64>) {
         let state_index = state.iter()
                                .position(|&x| x == state.iter().cloned().fold(f64::NEG_INFINITY, f64::max))
@@ -52,3 +58,82 @@ impl QLearning {
     }
 }
 
+
+// Learns online: each decision scores the previous action using the state it led to
+pub struct QLearningController {
+    pub qlearning: QLearning,
+    pub last_reward: Option<f64>,
+    previous: Option<(Array1<f64>, usize)>,
+    min_volume: f64,
+    max_volume: f64,
+}
+
+impl QLearningController {
+    pub fn new() -> Self {
+        Self::with_qlearning(QLearning::new(9, 5))
+    }
+
+    pub fn with_qlearning(qlearning: QLearning) -> Self {
+        Self {
+            qlearning,
+            last_reward: None,
+            previous: None,
+            min_volume: f64::MAX,
+            max_volume: f64::MIN,
+        }
+    }
+
+    fn calculate_reward(&mut self, total_volume: u32) -> f64 {
+        let total_volume = total_volume as f64;
+
+        // Update min and max volumes observed
+        if total_volume < self.min_volume {
+            self.min_volume = total_volume;
+        }
+        if total_volume > self.max_volume {
+            self.max_volume = total_volume;
+        }
+
+        // Normalize volume
+        let normalized_volume = if self.max_volume != self.min_volume {
+            (total_volume - self.min_volume) / (self.max_volume - self.min_volume)
+        } else {
+            0.0
+        };
+        -normalized_volume
+    }
+}
+
+impl Default for QLearningController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SignalController for QLearningController {
+    fn decision_interval(&self) -> Duration {
+        Duration::from_millis(100)
+    }
+
+    fn decide(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> SignalRequest {
+        let intersection = observation.intersection;
+        let state = intersection.get_state(observation.now);
+
+        if let Some((previous_state, previous_action)) = self.previous.take() {
+            let total_volume: u32 = intersection.intersection_volume.iter().sum();
+            let reward = self.calculate_reward(total_volume);
+            self.qlearning.update(&previous_state, previous_action, reward, &state);
+            self.last_reward = Some(reward);
+        }
+
+        let action = self.qlearning.choose_action(&state, rng);
+        self.previous = Some((state, action));
+
+        // Actions 0-3 toggle that approach's light, action 4 leaves everything as is
+        if action < 4 {
+            SignalRequest::Approach { approach: action, green: !intersection.stop_lights[action].green_requested }
+        } else {
+            SignalRequest::Hold
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use rand::{Rng, SeedableRng};
+use crate::qlearning::QLearningController;
+use crate::signal_controller::QueueCounts;
+use crate::signal_plan::APPROACHES;
+use crate::simulation::Simulation;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordFormat {
+    Csv,
+    JsonLines,
+}
+
+impl RecordFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(RecordFormat::Csv),
+            "jsonl" | "json" => Some(RecordFormat::JsonLines),
+            _ => None,
+        }
+    }
+
+    // .jsonl and .json files get JSON Lines, anything else CSV
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(RecordFormat::from_name)
+            .unwrap_or(RecordFormat::Csv)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum RecordedMetric {
+    Volume, // Vehicles spawned on each approach and not yet gone
+    Queue, // Per approach, as the controllers see it
+    Signal,
+    Reward, // Q-learning only; empty for other controllers
+    Epsilon, // Q-learning only; empty for other controllers
+}
+
+impl RecordedMetric {
+    pub const ALL: [RecordedMetric; 5] = [
+        RecordedMetric::Volume,
+        RecordedMetric::Queue,
+        RecordedMetric::Signal,
+        RecordedMetric::Reward,
+        RecordedMetric::Epsilon,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "volume" => Some(RecordedMetric::Volume),
+            "queue" => Some(RecordedMetric::Queue),
+            "signal" => Some(RecordedMetric::Signal),
+            "reward" => Some(RecordedMetric::Reward),
+            "epsilon" => Some(RecordedMetric::Epsilon),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecorderConfig {
+    pub path: PathBuf,
+    pub format: RecordFormat,
+    pub metrics: Vec<RecordedMetric>,
+    pub interval: Duration, // Simulated time between samples
+}
+
+impl RecorderConfig {
+    // Every metric once per simulated second, in the format the file extension suggests
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            format: RecordFormat::from_path(&path),
+            path,
+            metrics: RecordedMetric::ALL.to_vec(),
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    pub fn for_seed(&self, seed: u64) -> Self {
+        Self {
+            path: seeded_path(&self.path, seed),
+            ..self.clone()
+        }
+    }
+}
+
+// out.csv becomes out-7.csv, so replications in a batch do not overwrite each other
+pub fn seeded_path(path: &Path, seed: u64) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!("-{}", seed));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+enum Value {
+    Number(f64),
+    Text(String),
+    Missing,
+}
+
+pub struct Recorder {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    format: RecordFormat,
+    metrics: Vec<RecordedMetric>,
+    interval: Duration,
+    next_sample: Duration,
+    wrote_header: bool,
+}
+
+impl Recorder {
+    pub fn new(writer: Box<dyn Write + Send>, format: RecordFormat, metrics: Vec<RecordedMetric>, interval: Duration) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            format,
+            metrics,
+            interval,
+            next_sample: Duration::ZERO,
+            wrote_header: false,
+        }
+    }
+
+    pub fn create(config: &RecorderConfig) -> io::Result<Self> {
+        let file = File::create(&config.path)?;
+        Ok(Self::new(Box::new(file), config.format, config.metrics.clone(), config.interval))
+    }
+
+    // Called after every simulation step; writes a row whenever the next sample time has been reached
+    pub fn sample<R: Rng + SeedableRng>(&mut self, simulation: &Simulation<R>) -> io::Result<()> {
+        let now = simulation.clock.now();
+        if now < self.next_sample {
+            return Ok(());
+        }
+        while self.next_sample <= now {
+            self.next_sample += self.interval.max(Duration::from_nanos(1));
+        }
+
+        let row = self.collect(simulation);
+        match self.format {
+            RecordFormat::Csv => self.write_csv(&row),
+            RecordFormat::JsonLines => self.write_json(&row),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // With more than one intersection every column is prefixed with its node, as in n2_queue_0
+    fn collect<R: Rng + SeedableRng>(&self, simulation: &Simulation<R>) -> Vec<(String, Value)> {
+        let queue_counts = simulation.queue_counts();
+
+        let mut row = vec![("time".to_string(), Value::Number(simulation.clock.now().as_secs_f64()))];
+        for (intersection, QueueCounts { queues, .. }) in simulation.intersections.iter().zip(queue_counts) {
+            let prefix = if simulation.intersections.len() > 1 { format!("n{}_", intersection.node) } else { String::new() };
+            let manager = &intersection.manager;
+            let qlearning = (intersection.controller.as_ref() as &dyn Any).downcast_ref::<QLearningController>();
+
+            for metric in &self.metrics {
+                match metric {
+                    RecordedMetric::Volume => for approach in 0..APPROACHES {
+                        row.push((format!("{}volume_{}", prefix, approach), Value::Number(manager.intersection_volume[approach] as f64)));
+                    },
+                    RecordedMetric::Queue => for approach in 0..APPROACHES {
+                        let queue = queues[approach * 2] + queues[approach * 2 + 1];
+                        row.push((format!("{}queue_{}", prefix, approach), Value::Number(queue as f64)));
+                    },
+                    RecordedMetric::Signal => for (approach, stop_light) in manager.stop_lights.iter().enumerate() {
+                        row.push((format!("{}signal_{}", prefix, approach), Value::Text(format!("{:?}", stop_light.state))));
+                    },
+                    RecordedMetric::Reward => {
+                        let reward = qlearning.and_then(|controller| controller.last_reward);
+                        row.push((format!("{}reward", prefix), reward.map_or(Value::Missing, Value::Number)));
+                    },
+                    RecordedMetric::Epsilon => {
+                        let epsilon = qlearning.map(|controller| controller.qlearning.epsilon());
+                        row.push((format!("{}epsilon", prefix), epsilon.map_or(Value::Missing, Value::Number)));
+                    },
+                }
+            }
+        }
+        row
+    }
+
+    fn write_csv(&mut self, row: &[(String, Value)]) -> io::Result<()> {
+        if !self.wrote_header {
+            let header: Vec<&str> = row.iter().map(|(name, _)| name.as_str()).collect();
+            writeln!(self.writer, "{}", header.join(","))?;
+            self.wrote_header = true;
+        }
+
+        let fields: Vec<String> = row.iter().map(|(_, value)| match value {
+            Value::Number(number) => number.to_string(),
+            Value::Text(text) => text.clone(),
+            Value::Missing => String::new(),
+        }).collect();
+        writeln!(self.writer, "{}", fields.join(","))
+    }
+
+    fn write_json(&mut self, row: &[(String, Value)]) -> io::Result<()> {
+        let fields: Vec<String> = row.iter().map(|(name, value)| {
+            let value = match value {
+                Value::Number(number) if number.is_finite() => number.to_string(),
+                Value::Number(_) | Value::Missing => "null".to_string(),
+                Value::Text(text) => format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"")),
+            };
+            format!("\"{}\":{}", name, value)
+        }).collect();
+        writeln!(self.writer, "{{{}}}", fields.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::scenario::Scenario;
+    use crate::signal_controller::ControllerKind;
+    use super::*;
+
+    // Lets a test read back what the recorder wrote through its boxed writer
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Steps an empty intersection for 2.5 s at 100 ms, sampling after every step as a run does
+    fn record(format: RecordFormat, metrics: Vec<RecordedMetric>, interval: Duration) -> Vec<String> {
+        let mut scenario = Scenario::default();
+        scenario.demand.spawn_interval = Duration::MAX;
+        let mut simulation: Simulation = Simulation::new(None, &scenario, 0, &ControllerKind::QLearning);
+        let output = Shared::default();
+        let mut recorder = Recorder::new(Box::new(output.clone()), format, metrics, interval);
+        for _ in 0..25 {
+            simulation.update(Duration::from_millis(100));
+            recorder.sample(&simulation).unwrap();
+        }
+        recorder.flush().unwrap();
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn csv_writes_a_header_then_a_row_per_interval() {
+        let lines = record(RecordFormat::Csv, vec![RecordedMetric::Queue, RecordedMetric::Signal], Duration::from_millis(500));
+        assert_eq!(lines[0], "time,queue_0,queue_1,queue_2,queue_3,signal_0,signal_1,signal_2,signal_3");
+
+        // The first step, then every 500 ms of simulated time
+        let times: Vec<&str> = lines[1..].iter().map(|line| line.split(',').next().unwrap()).collect();
+        assert_eq!(times, ["0.1", "0.5", "1", "1.5", "2", "2.5"]);
+        for line in &lines[1..] {
+            let fields: Vec<&str> = line.split(',').collect();
+            assert_eq!(fields.len(), 9);
+            assert_eq!(&fields[1..5], ["0", "0", "0", "0"]);
+        }
+    }
+
+    #[test]
+    fn json_lines_write_one_object_per_interval() {
+        let lines = record(RecordFormat::JsonLines, RecordedMetric::ALL.to_vec(), Duration::from_secs(1));
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("{\"time\":0.1,\"volume_0\":0,"));
+        assert!(lines[1].starts_with("{\"time\":1,"));
+        assert!(lines[2].starts_with("{\"time\":2,"));
+        for line in &lines {
+            assert!(line.ends_with('}'));
+            assert!(line.contains("\"signal_0\":\""));
+            assert!(line.contains("\"epsilon\":"));
+            assert!(!line.contains("\"epsilon\":null"));
+        }
+    }
+}
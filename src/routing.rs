@@ -0,0 +1,213 @@
+use crate::network::{entrance_for, Endpoint, Network, Zone};
+use crate::vehicle::{Lane, TurnDirection};
+
+// Routes within this many pixels of the shortest count as equally short
+const ROUTE_TOLERANCE: f64 = 1.0;
+// Changing lanes on a link counts as this much extra distance, so routes avoid needless changes
+const LANE_CHANGE_COST: f64 = 25.0;
+
+// Trips between boundary zones in vehicles per hour: flows[origin][destination], zones as numbered
+// by Network::zones
+#[derive(Clone, PartialEq, Debug)]
+pub struct OdMatrix {
+    pub flows: Vec<Vec<f64>>,
+}
+
+impl OdMatrix {
+    pub fn new(flows: Vec<Vec<f64>>) -> Self {
+        Self { flows }
+    }
+
+    pub fn zones(&self) -> usize {
+        self.flows.len()
+    }
+
+    // Vehicles per hour leaving an origin for every destination together
+    pub fn origin_flow(&self, origin: usize) -> f64 {
+        self.flows[origin].iter().sum()
+    }
+}
+
+// The links a vehicle travels from its origin zone to its destination zone, with the lane it
+// approaches every node on the way in and the turn it makes there. Vehicles change lanes on the
+// links between nodes, never on the ones from the map edge.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Route {
+    pub origin: Zone,
+    pub destination: Zone,
+    pub links: Vec<usize>, // Origin link first, destination link last
+    pub lanes: Vec<Lane>, // One per node passed
+    pub turns: Vec<TurnDirection>, // One per node passed
+    pub length: f64, // Including lane change costs
+}
+
+impl Route {
+    // Entrance lane at the origin node
+    pub fn entrance(&self) -> u32 {
+        entrance_for(self.origin.approach, self.lanes[0])
+    }
+}
+
+// Shortest route by distance from origin to destination starting in lane, following the network's
+// lane connections. None if the destination cannot be reached from that lane.
+pub fn shortest_route(network: &Network, origin: Zone, destination: Zone, lane: Lane) -> Option<Route> {
+    // Dijkstra over (link, lane) states; the network is small enough to scan for the closest state
+    let state = |link: usize, lane: Lane| link * 2 + if lane == Lane::Left { 0 } else { 1 };
+    let states = network.links.len() * 2;
+    let mut distance = vec![f64::INFINITY; states];
+    // The state each one was reached from, by a turn at a node or else by a lane change
+    let mut previous: Vec<Option<(usize, Option<TurnDirection>)>> = vec![None; states];
+    let mut done = vec![false; states];
+
+    let start_link = network.link_into(origin.node, origin.approach);
+    let end_link = network.link_out_of(destination.node, destination.exit_heading());
+    let start = state(start_link, lane);
+    distance[start] = network.link_length(start_link);
+
+    while let Some(current) = (0..states)
+        .filter(|&s| !done[s] && distance[s].is_finite())
+        .min_by(|&a, &b| distance[a].total_cmp(&distance[b]))
+    {
+        done[current] = true;
+        let (link, lane) = (current / 2, if current % 2 == 0 { Lane::Left } else { Lane::Right });
+        if link == end_link {
+            return Some(trace_route(origin, destination, &previous, current, distance[current]));
+        }
+        let mut moves: Vec<(usize, Option<TurnDirection>, f64)> = network.connections.iter()
+            .filter(|connection| connection.from_link == link && connection.from_lane == lane)
+            .map(|connection| (state(connection.to_link, connection.to_lane), Some(connection.turn), network.link_length(connection.to_link)))
+            .collect();
+        if matches!(network.links[link].from, Endpoint::Node(_)) && matches!(network.links[link].to, Endpoint::Node(_)) {
+            let other = if lane == Lane::Left { Lane::Right } else { Lane::Left };
+            moves.push((state(link, other), None, LANE_CHANGE_COST));
+        }
+        for (next, turn, cost) in moves {
+            let through = distance[current] + cost;
+            if through < distance[next] {
+                distance[next] = through;
+                previous[next] = Some((current, turn));
+            }
+        }
+    }
+
+    None
+}
+
+fn trace_route(origin: Zone, destination: Zone, previous: &[Option<(usize, Option<TurnDirection>)>], end: usize, length: f64) -> Route {
+    let mut links = vec![end / 2];
+    let mut lanes = Vec::new();
+    let mut turns = Vec::new();
+    let mut current = end;
+    while let Some((before, turn)) = previous[current] {
+        if let Some(turn) = turn {
+            links.push(before / 2);
+            lanes.push(if before % 2 == 0 { Lane::Left } else { Lane::Right });
+            turns.push(turn);
+        }
+        current = before;
+    }
+    links.reverse();
+    lanes.reverse();
+    turns.reverse();
+
+    Route {
+        origin,
+        destination,
+        links,
+        lanes,
+        turns,
+        length,
+    }
+}
+
+// The shortest routes between every pair of zones, kept for both starting lanes when they tie
+pub struct RouteTable {
+    pub zones: Vec<Zone>,
+    routes: Vec<Vec<Vec<Route>>>,
+}
+
+impl RouteTable {
+    pub fn new(network: &Network) -> Self {
+        let zones = network.zones();
+        let routes = zones.iter().map(|&origin| zones.iter().map(|&destination| {
+            let candidates: Vec<Route> = [Lane::Left, Lane::Right].into_iter()
+                .filter_map(|lane| shortest_route(network, origin, destination, lane))
+                .collect();
+            let shortest = candidates.iter().map(|route| route.length).fold(f64::INFINITY, f64::min);
+            candidates.into_iter().filter(|route| route.length <= shortest + ROUTE_TOLERANCE).collect()
+        }).collect()).collect();
+
+        Self { zones, routes }
+    }
+
+    // Equally short routes between two zones, one per usable starting lane; empty if there is none
+    pub fn routes(&self, origin: usize, destination: usize) -> &[Route] {
+        &self.routes[origin][destination]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Geometry;
+
+    // Two intersections side by side, node 0 to the west
+    fn two_by_one() -> Network {
+        Network::grid(Geometry::default(), 2, 1, 200.0)
+    }
+
+    fn length(network: &Network, route: &Route) -> f64 {
+        route.links.iter().map(|&link| network.link_length(link)).sum()
+    }
+
+    #[test]
+    fn straight_across_both_nodes() {
+        let network = two_by_one();
+        let (origin, destination) = (Zone { node: 0, approach: 0 }, Zone { node: 1, approach: 2 });
+        let route = shortest_route(&network, origin, destination, Lane::Right).unwrap();
+        assert_eq!(route.turns, vec![TurnDirection::Straight, TurnDirection::Straight]);
+        assert_eq!(route.lanes, vec![Lane::Right, Lane::Right]);
+        assert_eq!(route.links.len(), 3);
+        assert_eq!(route.length, length(&network, &route));
+        assert_eq!(route.entrance(), 1);
+    }
+
+    #[test]
+    fn lane_changes_happen_between_nodes_at_a_cost() {
+        let network = two_by_one();
+        // East through node 0, then right at node 1 towards the south edge
+        let (origin, destination) = (Zone { node: 0, approach: 0 }, Zone { node: 1, approach: 3 });
+        let inside = shortest_route(&network, origin, destination, Lane::Left).unwrap();
+        assert_eq!(inside.turns, vec![TurnDirection::Straight, TurnDirection::Right]);
+        assert_eq!(inside.lanes, vec![Lane::Left, Lane::Right]);
+        assert_eq!(inside.length, length(&network, &inside) + LANE_CHANGE_COST);
+
+        let outside = shortest_route(&network, origin, destination, Lane::Right).unwrap();
+        assert_eq!(outside.lanes, vec![Lane::Right, Lane::Right]);
+        assert_eq!(outside.length, length(&network, &outside));
+    }
+
+    #[test]
+    fn no_lane_change_on_links_from_the_map_edge() {
+        let network = two_by_one();
+        let zone = Zone { node: 0, approach: 0 };
+        // From the inside lane a U-turn at the first node; from the outside lane the vehicle has to
+        // go on to node 1, move over and come back
+        let inside = shortest_route(&network, zone, zone, Lane::Left).unwrap();
+        assert_eq!(inside.turns, vec![TurnDirection::UTurn]);
+        let outside = shortest_route(&network, zone, zone, Lane::Right).unwrap();
+        assert_eq!(outside.turns, vec![TurnDirection::Straight, TurnDirection::UTurn, TurnDirection::Straight]);
+        assert_eq!(outside.lanes, vec![Lane::Right, Lane::Left, Lane::Left]);
+    }
+
+    #[test]
+    fn route_table_keeps_only_the_shortest_starting_lanes() {
+        let network = two_by_one();
+        let table = RouteTable::new(&network);
+        let zone = |node, approach| table.zones.iter().position(|&zone| zone == Zone { node, approach }).unwrap();
+        assert_eq!(table.routes(zone(0, 0), zone(1, 2)).len(), 2);
+        let to_south = table.routes(zone(0, 0), zone(1, 3));
+        assert_eq!(to_south.len(), 1);
+        assert_eq!(to_south[0].lanes[0], Lane::Right);
+    }
+}
@@ -0,0 +1,1139 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::Deserialize;
+use crate::arrivals::{ArrivalProcess, DemandPeriod, DemandProfile, DEFAULT_MIN_HEADWAY, DEFAULT_PLATOON_HEADWAY, DEFAULT_PLATOON_SIZE};
+use crate::actuated_controller::{ActuatedController, ActuatedPhase, Recall};
+use crate::car_following::{GippsParams, IdmParams, KraussParams, ModelKind, WiedemannParams};
+use crate::detector::{DetectorKind, DetectorPlacement};
+use crate::fixed_time_controller::{FixedTimeController, DEFAULT_SATURATION_FLOW};
+use crate::geometry::Geometry;
+use crate::network::Network;
+use crate::routing::{OdMatrix, RouteTable};
+use crate::turning::{TurnShares, TurningRatios};
+use crate::gap_acceptance::GapAcceptance;
+use crate::pedestrian::PedestrianSettings;
+use crate::headless::{RunConfig, StopCondition};
+use crate::max_pressure_controller::MaxPressureController;
+use crate::signal_controller::ControllerKind;
+use crate::signal_plan::{ConflictMatrix, Movement, Phase, PlanError, Protection, SignalPlan, APPROACHES};
+use crate::stop_light::SignalTimings;
+use crate::vehicle::{TurnDirection, VehicleType};
+
+// Vehicles come either from flows per boundary entrance, from an OD matrix, or failing both from
+// a fixed spawn timer
+#[derive(Clone, PartialEq, Debug)]
+pub struct Demand {
+    pub spawn_interval: Duration, // One vehicle on a random entrance every interval, without flows or an OD matrix
+    pub initial_speed: f64,
+    pub flows: Option<Vec<f64>>, // Vehicles per hour on each entrance in Network::boundary_entrances order
+    pub od: Option<OdMatrix>, // Route-following trips between boundary zones instead of random turns
+    pub arrivals: ArrivalProcess, // For flows and OD origins alike
+    pub profile: DemandProfile,
+}
+
+impl Demand {
+    // Flow in veh/h of the busiest entrance or OD origin at the profile's peak, if flows are given
+    pub fn peak_source_flow(&self) -> Option<f64> {
+        let base = match (&self.flows, &self.od) {
+            (Some(flows), _) => flows.iter().cloned().fold(0.0, f64::max),
+            (None, Some(od)) => (0..od.zones()).map(|origin| od.origin_flow(origin)).fold(0.0, f64::max),
+            (None, None) => return None,
+        };
+        Some(base * self.profile.peak_factor())
+    }
+
+    // Arrival processes with a minimum headway cannot deliver more than their max_flow
+    pub fn check_arrival_capacity(&self) -> Result<(), String> {
+        match self.peak_source_flow() {
+            Some(flow) if flow > self.arrivals.max_flow() => Err(format!(
+                "peak flow of {} veh/h exceeds the {:.0} veh/h the arrival process can deliver", flow, self.arrivals.max_flow())),
+            _ => Ok(()),
+        }
+    }
+
+    // Base flow in veh/h reaching each entrance lane from the map edge, the heaviest over all nodes.
+    // OD origins split their flow over both lanes, and the spawn timer over every boundary entrance.
+    pub fn entrance_flows(&self, network: &Network) -> [f64; 8] {
+        let entrances = network.boundary_entrances();
+        let per_entrance: Vec<f64> = match (&self.flows, &self.od) {
+            (Some(flows), _) => flows.clone(),
+            (None, Some(od)) => (0..od.zones()).flat_map(|origin| [od.origin_flow(origin) / 2.0; 2]).collect(),
+            (None, None) => vec![3600.0 / self.spawn_interval.as_secs_f64() / entrances.len() as f64; entrances.len()],
+        };
+
+        let mut flows = [0.0; 8];
+        for (&(_, entrance), flow) in entrances.iter().zip(per_entrance) {
+            flows[entrance as usize] = f64::max(flows[entrance as usize], flow);
+        }
+        flows
+    }
+}
+
+impl Default for Demand {
+    fn default() -> Self {
+        Self {
+            spawn_interval: Duration::from_millis(20),
+            initial_speed: 50.0,
+            flows: None,
+            od: None,
+            arrivals: ArrivalProcess::default(),
+            profile: DemandProfile::default(),
+        }
+    }
+}
+
+// Everything about the world a simulation is built from; controller and run length live in RunConfig
+#[derive(Clone)]
+pub struct Scenario {
+    pub network: Network,
+    pub demand: Demand,
+    pub vehicle_types: Vec<VehicleType>,
+    pub plan: SignalPlan,
+    pub timings: SignalTimings,
+    pub turning: Option<TurningRatios>, // Without, turns are drawn by lane; routed vehicles follow their route
+    pub gap_acceptance: GapAcceptance,
+    pub pedestrians: PedestrianSettings, // No pedestrians unless given a flow
+    pub detectors: Option<Vec<Vec<DetectorPlacement>>>, // Per node, instead of the default layout at every node
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self {
+            network: Network::single(Geometry::default()),
+            demand: Demand::default(),
+            vehicle_types: vec![VehicleType::default()],
+            plan: SignalPlan::two_phase(),
+            timings: SignalTimings::default(),
+            turning: None,
+            gap_acceptance: GapAcceptance::default(),
+            pedestrians: PedestrianSettings::default(),
+            detectors: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io { path: PathBuf, source: io::Error },
+    Parse(String),
+    Invalid { field: String, message: String },
+    Plan(PlanError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io { path, source } => write!(f, "cannot read scenario {}: {}", path.display(), source),
+            ScenarioError::Parse(message) => write!(f, "invalid scenario file: {}", message),
+            ScenarioError::Invalid { field, message } => write!(f, "invalid scenario: {} {}", field, message),
+            ScenarioError::Plan(err) => write!(f, "invalid scenario signal plan: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+fn invalid(field: &str, message: &str) -> ScenarioError {
+    ScenarioError::Invalid { field: field.to_string(), message: message.to_string() }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScenarioFormat {
+    Toml,
+    Json,
+}
+
+// Reads a TOML or JSON (by extension) scenario into a run configuration and a replication count
+pub fn load(path: &Path) -> Result<(RunConfig, usize), ScenarioError> {
+    let text = std::fs::read_to_string(path).map_err(|source| ScenarioError::Io { path: path.to_path_buf(), source })?;
+    let format = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => ScenarioFormat::Json,
+        _ => ScenarioFormat::Toml,
+    };
+    parse(&text, format)
+}
+
+pub fn parse(text: &str, format: ScenarioFormat) -> Result<(RunConfig, usize), ScenarioError> {
+    let file: ScenarioFile = match format {
+        ScenarioFormat::Toml => toml::from_str(text).map_err(|err| ScenarioError::Parse(err.to_string()))?,
+        ScenarioFormat::Json => serde_json::from_str(text).map_err(|err| ScenarioError::Parse(err.to_string()))?,
+    };
+    file.into_run()
+}
+
+// The file layout. Every section and field is optional and defaults to the built-in scenario;
+// times are in simulated seconds and distances in pixels.
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ScenarioFile {
+    run: RunSection,
+    geometry: GeometrySection,
+    network: NetworkSection,
+    demand: DemandSection,
+    vehicle_types: Option<Vec<VehicleTypeSection>>,
+    signals: SignalSection,
+    controller: ControllerSection,
+    turning: Option<Vec<TurningSection>>,
+    pedestrians: PedestrianSection,
+    detectors: Option<Vec<DetectorSection>>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RunSection {
+    duration: Option<f64>,
+    steps: Option<usize>,
+    dt: f64,
+    seed: u64,
+    runs: usize,
+}
+
+impl Default for RunSection {
+    fn default() -> Self {
+        let config = RunConfig::default();
+        Self {
+            duration: None,
+            steps: None,
+            dt: config.dt.as_secs_f64(),
+            seed: config.seed,
+            runs: 1,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GeometrySection {
+    width: u32,
+    height: u32,
+    lane_width: f64,
+    box_size: f64,
+    stop_line_setback: f64,
+}
+
+impl Default for GeometrySection {
+    fn default() -> Self {
+        let geometry = Geometry::default();
+        Self {
+            width: geometry.width,
+            height: geometry.height,
+            lane_width: geometry.lane_width,
+            box_size: geometry.box_size,
+            stop_line_setback: geometry.stop_line_setback,
+        }
+    }
+}
+
+// Intersections on a grid; one by one is the classic single intersection
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NetworkSection {
+    columns: usize,
+    rows: usize,
+    spacing: f64, // Between neighbouring intersection centres
+}
+
+impl Default for NetworkSection {
+    fn default() -> Self {
+        Self {
+            columns: 1,
+            rows: 1,
+            spacing: 200.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DemandSection {
+    spawn_interval: f64,
+    initial_speed: f64,
+    flow: Option<f64>, // Vehicles per hour on every boundary entrance
+    flows: Option<Vec<f64>>, // Or one per boundary entrance
+    od: Option<Vec<Vec<f64>>>, // Vehicles per hour, one row per origin zone and one column per destination
+    arrivals: ArrivalSection,
+    profile: Option<Vec<PeriodSection>>,
+}
+
+impl Default for DemandSection {
+    fn default() -> Self {
+        let demand = Demand::default();
+        Self {
+            spawn_interval: demand.spawn_interval.as_secs_f64(),
+            initial_speed: demand.initial_speed,
+            flow: None,
+            flows: None,
+            od: None,
+            arrivals: ArrivalSection::default(),
+            profile: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+enum ArrivalSection {
+    Deterministic,
+    #[default]
+    Poisson,
+    ShiftedExponential {
+        min_headway: Option<f64>,
+    },
+    Platooned {
+        size: Option<usize>,
+        headway: Option<f64>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PeriodSection {
+    #[serde(default)]
+    name: String,
+    duration: f64,
+    factor: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VehicleTypeSection {
+    length: u32,
+    width: u32,
+    #[serde(default = "default_share")]
+    share: f64,
+    #[serde(default = "default_model")]
+    model: String,
+    // Parameters of the chosen model; anything left out keeps the model's default
+    idm: Option<IdmSection>,
+    gipps: Option<GippsSection>,
+    krauss: Option<KraussSection>,
+    wiedemann: Option<WiedemannSection>,
+}
+
+fn default_share() -> f64 {
+    1.0
+}
+
+fn default_model() -> String {
+    "idm".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IdmSection {
+    desired_speed: f64,
+    max_acceleration: f64,
+    comfortable_deceleration: f64,
+    minimum_gap: f64,
+    time_headway: f64,
+}
+
+impl Default for IdmSection {
+    fn default() -> Self {
+        let params = IdmParams::default();
+        Self {
+            desired_speed: params.desired_speed,
+            max_acceleration: params.max_acceleration,
+            comfortable_deceleration: params.comfortable_deceleration,
+            minimum_gap: params.minimum_gap,
+            time_headway: params.time_headway,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GippsSection {
+    desired_speed: f64,
+    max_acceleration: f64,
+    max_deceleration: f64,
+    leader_deceleration_estimate: f64,
+    minimum_gap: f64,
+    reaction_time: f64,
+}
+
+impl Default for GippsSection {
+    fn default() -> Self {
+        let params = GippsParams::default();
+        Self {
+            desired_speed: params.desired_speed,
+            max_acceleration: params.max_acceleration,
+            max_deceleration: params.max_deceleration,
+            leader_deceleration_estimate: params.leader_deceleration_estimate,
+            minimum_gap: params.minimum_gap,
+            reaction_time: params.reaction_time,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KraussSection {
+    desired_speed: f64,
+    max_acceleration: f64,
+    max_deceleration: f64,
+    minimum_gap: f64,
+    reaction_time: f64,
+    imperfection: f64,
+}
+
+impl Default for KraussSection {
+    fn default() -> Self {
+        let params = KraussParams::default();
+        Self {
+            desired_speed: params.desired_speed,
+            max_acceleration: params.max_acceleration,
+            max_deceleration: params.max_deceleration,
+            minimum_gap: params.minimum_gap,
+            reaction_time: params.reaction_time,
+            imperfection: params.imperfection,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WiedemannSection {
+    desired_speed: f64,
+    max_acceleration: f64,
+    max_deceleration: f64,
+    standstill_distance: f64,
+    following_factor: f64,
+    perception_distance: f64,
+    oscillation_acceleration: f64,
+}
+
+impl Default for WiedemannSection {
+    fn default() -> Self {
+        let params = WiedemannParams::default();
+        Self {
+            desired_speed: params.desired_speed,
+            max_acceleration: params.max_acceleration,
+            max_deceleration: params.max_deceleration,
+            standstill_distance: params.standstill_distance,
+            following_factor: params.following_factor,
+            perception_distance: params.perception_distance,
+            oscillation_acceleration: params.oscillation_acceleration,
+        }
+    }
+}
+
+// Shares of one approach's vehicles by movement, at one node or at every node
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TurningSection {
+    node: Option<usize>,
+    approach: usize,
+    #[serde(default)]
+    left: f64,
+    #[serde(default)]
+    through: f64,
+    #[serde(default)]
+    right: f64,
+    #[serde(default)]
+    u_turn: f64,
+}
+
+// A detector on one entrance lane, at one node or at every node. Setback and length default to
+// where the default layout puts that kind.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetectorSection {
+    node: Option<usize>,
+    kind: DetectorKindName,
+    entrance: u32,
+    setback: Option<f64>,
+    length: Option<u32>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum DetectorKindName {
+    Loop,
+    Area,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SignalSection {
+    plan: Option<String>,
+    phases: Option<Vec<PhaseSection>>,
+    min_green: f64,
+    yellow: f64,
+    all_red: f64,
+    critical_gap: f64, // Left turns and U-turns need this long before the next opposing vehicle arrives
+    right_turn_on_red: bool,
+}
+
+impl Default for SignalSection {
+    fn default() -> Self {
+        let timings = SignalTimings::default();
+        Self {
+            plan: None,
+            phases: None,
+            min_green: timings.min_green.as_secs_f64(),
+            yellow: timings.yellow.as_secs_f64(),
+            all_red: timings.all_red.as_secs_f64(),
+            critical_gap: GapAcceptance::default().critical_gap.as_secs_f64(),
+            right_turn_on_red: GapAcceptance::default().right_turn_on_red,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PedestrianSection {
+    flow: f64, // Per crosswalk, per hour
+    walking_speed: f64,
+    walk: f64,
+}
+
+impl Default for PedestrianSection {
+    fn default() -> Self {
+        let settings = PedestrianSettings::default();
+        Self {
+            flow: settings.flow,
+            walking_speed: settings.walking_speed,
+            walk: settings.walk.as_secs_f64(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PhaseSection {
+    name: String,
+    movements: Vec<MovementSection>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MovementSection {
+    approach: usize,
+    turn: TurnName,
+    #[serde(default)]
+    permitted: bool,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum TurnName {
+    Left,
+    Straight,
+    Right,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum RecallName {
+    Off,
+    Minimum,
+    Maximum,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+enum ControllerSection {
+    #[default]
+    Qlearning,
+    Fixed {
+        cycle: f64,
+        splits: Option<Vec<f64>>,
+        #[serde(default)]
+        offset: f64,
+    },
+    Actuated {
+        min_green: Option<f64>,
+        max_green: Option<f64>,
+        passage_time: Option<f64>,
+        recall: Option<RecallName>,
+    },
+    MaxPressure {
+        interval: Option<f64>,
+    },
+    // Fixed time with Webster's cycle and splits for the scenario's demand and plan
+    Webster {
+        saturation_flow: Option<f64>,
+        #[serde(default)]
+        offset: f64,
+    },
+}
+
+fn non_negative_seconds(field: &str, value: f64) -> Result<Duration, ScenarioError> {
+    if !value.is_finite() || value < 0.0 {
+        return Err(invalid(field, &format!("must be zero or more seconds, got {}", value)));
+    }
+    Ok(Duration::from_secs_f64(value))
+}
+
+fn positive_seconds(field: &str, value: f64) -> Result<Duration, ScenarioError> {
+    if !value.is_finite() || value <= 0.0 {
+        return Err(invalid(field, &format!("must be a positive number of seconds, got {}", value)));
+    }
+    Ok(Duration::from_secs_f64(value))
+}
+
+fn positive(field: &str, value: f64) -> Result<f64, ScenarioError> {
+    if !value.is_finite() || value <= 0.0 {
+        return Err(invalid(field, &format!("must be positive, got {}", value)));
+    }
+    Ok(value)
+}
+
+fn non_negative(field: &str, value: f64) -> Result<f64, ScenarioError> {
+    if !value.is_finite() || value < 0.0 {
+        return Err(invalid(field, &format!("must not be negative, got {}", value)));
+    }
+    Ok(value)
+}
+
+impl ScenarioFile {
+    fn into_run(self) -> Result<(RunConfig, usize), ScenarioError> {
+        let geometry = self.geometry.validate()?;
+        let network = self.network.validate(geometry)?;
+        let demand = self.demand.validate(&network)?;
+        let vehicle_types = match self.vehicle_types {
+            Some(types) => validate_vehicle_types(types)?,
+            None => vec![VehicleType::default()],
+        };
+        let (plan, timings, gap_acceptance) = self.signals.validate(&geometry)?;
+        let controller = self.controller.validate(&plan, &timings, &demand, &network)?;
+        let turning = self.turning.map(|sections| validate_turning(sections, &network)).transpose()?;
+        let pedestrians = self.pedestrians.validate()?;
+        let detectors = self.detectors.map(|sections| validate_detectors(sections, &network)).transpose()?;
+
+        let run = self.run;
+        let stop = match (run.duration, run.steps) {
+            (Some(_), Some(_)) => return Err(invalid("run", "sets both duration and steps; pick one")),
+            (Some(duration), None) => StopCondition::SimulatedTime(positive_seconds("run.duration", duration)?),
+            (None, Some(0)) => return Err(invalid("run.steps", "must be at least 1")),
+            (None, Some(steps)) => StopCondition::Steps(steps),
+            (None, None) => RunConfig::default().stop,
+        };
+        if run.runs == 0 {
+            return Err(invalid("run.runs", "must be at least 1"));
+        }
+
+        let config = RunConfig {
+            stop,
+            dt: positive_seconds("run.dt", run.dt)?,
+            seed: run.seed,
+            scenario: Scenario { network, demand, vehicle_types, plan, timings, turning, gap_acceptance, pedestrians, detectors },
+            controller,
+            ..RunConfig::default()
+        };
+        Ok((config, run.runs))
+    }
+}
+
+impl GeometrySection {
+    fn validate(self) -> Result<Geometry, ScenarioError> {
+        if !(self.lane_width.is_finite() && self.lane_width > 0.0) {
+            return Err(invalid("geometry.lane_width", "must be positive"));
+        }
+        // Two lanes each way have to fit across the box
+        if !(self.box_size.is_finite() && self.box_size >= self.lane_width * 4.0) {
+            return Err(invalid("geometry.box_size", &format!("must be at least four lane widths ({})", self.lane_width * 4.0)));
+        }
+        if !(self.stop_line_setback.is_finite() && self.stop_line_setback >= 0.0) {
+            return Err(invalid("geometry.stop_line_setback", "must not be negative"));
+        }
+        let needed = self.box_size + 2.0 * self.stop_line_setback;
+        if (self.width.min(self.height) as f64) <= needed {
+            return Err(invalid("geometry", &format!("width and height must both exceed the box plus stop lines ({})", needed)));
+        }
+
+        Ok(Geometry {
+            width: self.width,
+            height: self.height,
+            center: (self.width as f64 / 2.0, self.height as f64 / 2.0),
+            lane_width: self.lane_width,
+            box_size: self.box_size,
+            stop_line_setback: self.stop_line_setback,
+        })
+    }
+}
+
+impl NetworkSection {
+    fn validate(self, geometry: Geometry) -> Result<Network, ScenarioError> {
+        if self.columns == 0 || self.rows == 0 {
+            return Err(invalid("network", "needs at least one column and one row"));
+        }
+        // Each intersection with its stop lines has to fit between its neighbours and on the map
+        let footprint = geometry.box_size + 2.0 * geometry.stop_line_setback;
+        if (self.columns > 1 || self.rows > 1) && !(self.spacing.is_finite() && self.spacing > footprint) {
+            return Err(invalid("network.spacing", &format!("must exceed the box plus stop lines ({})", footprint)));
+        }
+        let span_x = (self.columns - 1) as f64 * self.spacing + footprint;
+        let span_y = (self.rows - 1) as f64 * self.spacing + footprint;
+        if span_x >= geometry.width as f64 || span_y >= geometry.height as f64 {
+            return Err(invalid("network", &format!("needs a map of more than {} by {}", span_x, span_y)));
+        }
+
+        Ok(Network::grid(geometry, self.columns, self.rows, self.spacing))
+    }
+}
+
+impl DemandSection {
+    fn validate(self, network: &Network) -> Result<Demand, ScenarioError> {
+        if !(self.initial_speed.is_finite() && self.initial_speed >= 0.0) {
+            return Err(invalid("demand.initial_speed", "must not be negative"));
+        }
+        let entrances = network.boundary_entrances().len();
+        let flows = match (self.flow, self.flows) {
+            (Some(_), Some(_)) => return Err(invalid("demand", "sets both flow and flows; pick one")),
+            (Some(flow), None) => Some(vec![flow; entrances]),
+            (None, flows) => flows,
+        };
+        if let Some(flows) = &flows {
+            if self.od.is_some() {
+                return Err(invalid("demand", "sets both entrance flows and an OD matrix; pick one"));
+            }
+            if flows.len() != entrances {
+                return Err(invalid("demand.flows", &format!("must list {} flows, one per entrance on the map edge", entrances)));
+            }
+            if flows.iter().any(|flow| !(flow.is_finite() && *flow >= 0.0)) {
+                return Err(invalid("demand.flows", "must not be negative"));
+            }
+        }
+
+        let demand = Demand {
+            spawn_interval: positive_seconds("demand.spawn_interval", self.spawn_interval)?,
+            initial_speed: self.initial_speed,
+            flows,
+            od: self.od.map(|flows| validate_od(flows, network)).transpose()?,
+            arrivals: self.arrivals.validate()?,
+            profile: DemandProfile::new(self.profile.unwrap_or_default().into_iter().enumerate()
+                .map(|(i, period)| period.validate(i))
+                .collect::<Result<_, _>>()?),
+        };
+        demand.check_arrival_capacity().map_err(|message| invalid("demand.arrivals", &message))?;
+        Ok(demand)
+    }
+}
+
+impl ArrivalSection {
+    fn validate(self) -> Result<ArrivalProcess, ScenarioError> {
+        Ok(match self {
+            ArrivalSection::Deterministic => ArrivalProcess::Deterministic,
+            ArrivalSection::Poisson => ArrivalProcess::Poisson,
+            ArrivalSection::ShiftedExponential { min_headway } => ArrivalProcess::ShiftedExponential {
+                min_headway: match min_headway {
+                    Some(min_headway) => non_negative_seconds("demand.arrivals.min_headway", min_headway)?,
+                    None => DEFAULT_MIN_HEADWAY,
+                },
+            },
+            ArrivalSection::Platooned { size, headway } => {
+                let size = size.unwrap_or(DEFAULT_PLATOON_SIZE);
+                if size == 0 {
+                    return Err(invalid("demand.arrivals.size", "must be at least 1"));
+                }
+                ArrivalProcess::Platooned {
+                    size,
+                    headway: match headway {
+                        Some(headway) => non_negative_seconds("demand.arrivals.headway", headway)?,
+                        None => DEFAULT_PLATOON_HEADWAY,
+                    },
+                }
+            }
+        })
+    }
+}
+
+impl PeriodSection {
+    fn validate(self, i: usize) -> Result<DemandPeriod, ScenarioError> {
+        let field = format!("demand.profile[{}]", i);
+        if !(self.factor.is_finite() && self.factor >= 0.0) {
+            return Err(invalid(&format!("{}.factor", field), "must not be negative"));
+        }
+        Ok(DemandPeriod {
+            name: self.name,
+            duration: positive_seconds(&format!("{}.duration", field), self.duration)?,
+            factor: self.factor,
+        })
+    }
+}
+
+// Every zone needs a row and a column, and every pair with trips a route between them
+fn validate_od(flows: Vec<Vec<f64>>, network: &Network) -> Result<OdMatrix, ScenarioError> {
+    let zones = network.zones().len();
+    if flows.len() != zones || flows.iter().any(|row| row.len() != zones) {
+        return Err(invalid("demand.od", &format!("must be {} by {}, one row and column per zone on the map edge", zones, zones)));
+    }
+    let routes = RouteTable::new(network);
+    for (origin, row) in flows.iter().enumerate() {
+        for (destination, &flow) in row.iter().enumerate() {
+            if !(flow.is_finite() && flow >= 0.0) {
+                return Err(invalid(&format!("demand.od[{}][{}]", origin, destination), "must not be negative"));
+            }
+            if flow > 0.0 && routes.routes(origin, destination).is_empty() {
+                return Err(invalid(&format!("demand.od[{}][{}]", origin, destination), "has trips but no route from the origin to the destination"));
+            }
+        }
+    }
+    Ok(OdMatrix::new(flows))
+}
+
+// Approaches left out keep equal left, through and right shares; later entries override earlier ones
+fn validate_turning(sections: Vec<TurningSection>, network: &Network) -> Result<TurningRatios, ScenarioError> {
+    let nodes = network.nodes.len();
+    let mut ratios = TurningRatios::new(nodes);
+    for (i, section) in sections.into_iter().enumerate() {
+        let field = format!("turning[{}]", i);
+        if section.approach >= APPROACHES {
+            return Err(invalid(&format!("{}.approach", field), &format!("must be below {}", APPROACHES)));
+        }
+        if section.node.is_some_and(|node| node >= nodes) {
+            return Err(invalid(&format!("{}.node", field), &format!("must be below {}, the number of intersections", nodes)));
+        }
+        let shares = TurnShares { left: section.left, through: section.through, right: section.right, u_turn: section.u_turn };
+        if [shares.left, shares.through, shares.right, shares.u_turn].iter().any(|share| !(share.is_finite() && *share >= 0.0)) {
+            return Err(invalid(&field, "shares must not be negative"));
+        }
+        if shares.total() <= 0.0 {
+            return Err(invalid(&field, "needs at least one movement with a positive share"));
+        }
+        match section.node {
+            Some(node) => ratios.set(node, section.approach, shares),
+            None => (0..nodes).for_each(|node| ratios.set(node, section.approach, shares)),
+        }
+    }
+    Ok(ratios)
+}
+
+fn validate_detectors(sections: Vec<DetectorSection>, network: &Network) -> Result<Vec<Vec<DetectorPlacement>>, ScenarioError> {
+    let nodes = network.nodes.len();
+    let mut layouts = vec![Vec::new(); nodes];
+    for (i, section) in sections.into_iter().enumerate() {
+        let field = format!("detectors[{}]", i);
+        if section.node.is_some_and(|node| node >= nodes) {
+            return Err(invalid(&format!("{}.node", field), &format!("must be below {}, the number of intersections", nodes)));
+        }
+        if section.entrance >= 2 * APPROACHES as u32 {
+            return Err(invalid(&format!("{}.entrance", field), &format!("must be below {}", 2 * APPROACHES)));
+        }
+        let kind = match section.kind {
+            DetectorKindName::Loop => DetectorKind::Loop,
+            DetectorKindName::Area => DetectorKind::Area,
+        };
+        let mut placement = DetectorPlacement::new(kind, section.entrance);
+        if let Some(setback) = section.setback {
+            placement.setback = non_negative(&format!("{}.setback", field), setback)?;
+        }
+        if let Some(length) = section.length {
+            if length == 0 {
+                return Err(invalid(&format!("{}.length", field), "must be at least 1"));
+            }
+            placement.length = length;
+        }
+        match section.node {
+            Some(node) => layouts[node].push(placement),
+            None => layouts.iter_mut().for_each(|layout| layout.push(placement)),
+        }
+    }
+    Ok(layouts)
+}
+
+fn validate_vehicle_types(types: Vec<VehicleTypeSection>) -> Result<Vec<VehicleType>, ScenarioError> {
+    if types.is_empty() {
+        return Err(invalid("vehicle_types", "must list at least one vehicle type"));
+    }
+
+    let mut vehicle_types = Vec::new();
+    for (i, section) in types.into_iter().enumerate() {
+        let field = format!("vehicle_types[{}]", i);
+        if section.length == 0 || section.width == 0 {
+            return Err(invalid(&field, "must have a positive length and width"));
+        }
+        if !(section.share.is_finite() && section.share >= 0.0) {
+            return Err(invalid(&format!("{}.share", field), "must not be negative"));
+        }
+        // Vehicle bounds are width along the heading and height across it
+        let (width, height, share) = (section.length, section.width, section.share);
+        let model = section.model(&field)?;
+        vehicle_types.push(VehicleType { width, height, share, model });
+    }
+
+    if vehicle_types.iter().all(|vehicle_type| vehicle_type.share == 0.0) {
+        return Err(invalid("vehicle_types", "must have at least one type with a positive share"));
+    }
+    Ok(vehicle_types)
+}
+
+impl VehicleTypeSection {
+    // The named model with its parameters; only the table of that model may be given
+    fn model(self, field: &str) -> Result<ModelKind, ScenarioError> {
+        let name = self.model.as_str();
+        if ModelKind::from_name(name).is_none() {
+            return Err(invalid(&format!("{}.model", field), &format!("'{}' is not one of idm, gipps, krauss, wiedemann", name)));
+        }
+        let tables = [("idm", self.idm.is_some()), ("gipps", self.gipps.is_some()), ("krauss", self.krauss.is_some()), ("wiedemann", self.wiedemann.is_some())];
+        if let Some((table, _)) = tables.iter().find(|&&(table, given)| given && table != name) {
+            return Err(invalid(&format!("{}.{}", field, table), &format!("does not apply to model '{}'", name)));
+        }
+
+        let field = format!("{}.{}", field, name);
+        Ok(match name {
+            "idm" => ModelKind::Idm(self.idm.unwrap_or_default().validate(&field)?),
+            "gipps" => ModelKind::Gipps(self.gipps.unwrap_or_default().validate(&field)?),
+            "krauss" => ModelKind::Krauss(self.krauss.unwrap_or_default().validate(&field)?),
+            "wiedemann" => ModelKind::Wiedemann(self.wiedemann.unwrap_or_default().validate(&field)?),
+            _ => unreachable!(),
+        })
+    }
+}
+
+impl IdmSection {
+    fn validate(self, field: &str) -> Result<IdmParams, ScenarioError> {
+        Ok(IdmParams {
+            desired_speed: positive(&format!("{}.desired_speed", field), self.desired_speed)?,
+            max_acceleration: positive(&format!("{}.max_acceleration", field), self.max_acceleration)?,
+            comfortable_deceleration: positive(&format!("{}.comfortable_deceleration", field), self.comfortable_deceleration)?,
+            minimum_gap: non_negative(&format!("{}.minimum_gap", field), self.minimum_gap)?,
+            time_headway: non_negative(&format!("{}.time_headway", field), self.time_headway)?,
+        })
+    }
+}
+
+impl GippsSection {
+    fn validate(self, field: &str) -> Result<GippsParams, ScenarioError> {
+        Ok(GippsParams {
+            desired_speed: positive(&format!("{}.desired_speed", field), self.desired_speed)?,
+            max_acceleration: positive(&format!("{}.max_acceleration", field), self.max_acceleration)?,
+            max_deceleration: positive(&format!("{}.max_deceleration", field), self.max_deceleration)?,
+            leader_deceleration_estimate: positive(&format!("{}.leader_deceleration_estimate", field), self.leader_deceleration_estimate)?,
+            minimum_gap: non_negative(&format!("{}.minimum_gap", field), self.minimum_gap)?,
+            // Without a reaction time the free-flow speed never grows
+            reaction_time: positive(&format!("{}.reaction_time", field), self.reaction_time)?,
+        })
+    }
+}
+
+impl KraussSection {
+    fn validate(self, field: &str) -> Result<KraussParams, ScenarioError> {
+        if !(self.imperfection.is_finite() && (0.0..=1.0).contains(&self.imperfection)) {
+            return Err(invalid(&format!("{}.imperfection", field), &format!("must be between 0 and 1, got {}", self.imperfection)));
+        }
+        Ok(KraussParams {
+            desired_speed: positive(&format!("{}.desired_speed", field), self.desired_speed)?,
+            max_acceleration: positive(&format!("{}.max_acceleration", field), self.max_acceleration)?,
+            max_deceleration: positive(&format!("{}.max_deceleration", field), self.max_deceleration)?,
+            minimum_gap: non_negative(&format!("{}.minimum_gap", field), self.minimum_gap)?,
+            // The safe speed divides by it when standing
+            reaction_time: positive(&format!("{}.reaction_time", field), self.reaction_time)?,
+            imperfection: self.imperfection,
+        })
+    }
+}
+
+impl WiedemannSection {
+    fn validate(self, field: &str) -> Result<WiedemannParams, ScenarioError> {
+        Ok(WiedemannParams {
+            desired_speed: positive(&format!("{}.desired_speed", field), self.desired_speed)?,
+            max_acceleration: positive(&format!("{}.max_acceleration", field), self.max_acceleration)?,
+            max_deceleration: positive(&format!("{}.max_deceleration", field), self.max_deceleration)?,
+            standstill_distance: non_negative(&format!("{}.standstill_distance", field), self.standstill_distance)?,
+            following_factor: non_negative(&format!("{}.following_factor", field), self.following_factor)?,
+            perception_distance: non_negative(&format!("{}.perception_distance", field), self.perception_distance)?,
+            oscillation_acceleration: non_negative(&format!("{}.oscillation_acceleration", field), self.oscillation_acceleration)?,
+        })
+    }
+}
+
+impl PedestrianSection {
+    fn validate(self) -> Result<PedestrianSettings, ScenarioError> {
+        if !(self.flow.is_finite() && self.flow >= 0.0) {
+            return Err(invalid("pedestrians.flow", "must not be negative"));
+        }
+        if !(self.walking_speed.is_finite() && self.walking_speed > 0.0) {
+            return Err(invalid("pedestrians.walking_speed", "must be positive"));
+        }
+        Ok(PedestrianSettings {
+            flow: self.flow,
+            walking_speed: self.walking_speed,
+            walk: positive_seconds("pedestrians.walk", self.walk)?,
+        })
+    }
+}
+
+impl SignalSection {
+    fn validate(self, geometry: &Geometry) -> Result<(SignalPlan, SignalTimings, GapAcceptance), ScenarioError> {
+        let timings = SignalTimings {
+            min_green: non_negative_seconds("signals.min_green", self.min_green)?,
+            yellow: positive_seconds("signals.yellow", self.yellow)?,
+            all_red: non_negative_seconds("signals.all_red", self.all_red)?,
+        };
+        let gap_acceptance = GapAcceptance {
+            critical_gap: non_negative_seconds("signals.critical_gap", self.critical_gap)?,
+            right_turn_on_red: self.right_turn_on_red,
+        };
+
+        let plan = match (self.plan.as_deref(), self.phases) {
+            (Some(_), Some(_)) => return Err(invalid("signals", "sets both plan and phases; pick one")),
+            (Some("two-phase") | None, None) => SignalPlan::two_phase(),
+            (Some("split-phase"), None) => SignalPlan::split_phase(),
+            (Some("protected-left"), None) => SignalPlan::protected_left(),
+            (Some(other), None) => return Err(invalid("signals.plan", &format!("'{}' is not one of two-phase, split-phase, protected-left", other))),
+            (None, Some(phases)) => SignalPlan {
+                phases: phases.into_iter().map(|phase| Phase {
+                    name: phase.name,
+                    movements: phase.movements.iter().map(|movement| {
+                        let turn = match movement.turn {
+                            TurnName::Left => TurnDirection::Left,
+                            TurnName::Straight => TurnDirection::Straight,
+                            TurnName::Right => TurnDirection::Right,
+                        };
+                        let protection = if movement.permitted { Protection::Permitted } else { Protection::Protected };
+                        (Movement { approach: movement.approach, turn }, protection)
+                    }).collect(),
+                }).collect(),
+            },
+        };
+        plan.validate(&ConflictMatrix::from_geometry(geometry)).map_err(ScenarioError::Plan)?;
+
+        Ok((plan, timings, gap_acceptance))
+    }
+}
+
+impl ControllerSection {
+    fn validate(self, plan: &SignalPlan, timings: &SignalTimings, demand: &Demand, network: &Network) -> Result<ControllerKind, ScenarioError> {
+        let phase_count = plan.phases.len();
+
+        let controller = match self {
+            ControllerSection::Qlearning => ControllerKind::QLearning,
+            ControllerSection::Fixed { cycle, splits, offset } => {
+                let cycle = positive_seconds("controller.cycle", cycle)?;
+                let splits = splits.unwrap_or_else(|| vec![1.0; phase_count]);
+                if splits.len() != phase_count {
+                    return Err(invalid("controller.splits", &format!("has {} entries but the plan has {} phases", splits.len(), phase_count)));
+                }
+                if splits.iter().any(|split| !(split.is_finite() && *split > 0.0)) {
+                    return Err(invalid("controller.splits", "must all be positive"));
+                }
+                let intergreen = timings.yellow + timings.all_red;
+                if cycle <= intergreen * phase_count as u32 {
+                    return Err(invalid("controller.cycle", &format!("must be longer than the plan's lost time ({}s)", (intergreen * phase_count as u32).as_secs_f64())));
+                }
+                let offset = non_negative_seconds("controller.offset", offset)?;
+                ControllerKind::FixedTime(FixedTimeController::new(cycle, &splits, offset, intergreen))
+            },
+            ControllerSection::Actuated { min_green, max_green, passage_time, recall } => {
+                let mut phase = ActuatedPhase::default();
+                if let Some(min_green) = min_green {
+                    phase.min_green = non_negative_seconds("controller.min_green", min_green)?;
+                }
+                if let Some(max_green) = max_green {
+                    phase.max_green = positive_seconds("controller.max_green", max_green)?;
+                }
+                if let Some(passage_time) = passage_time {
+                    phase.passage_time = positive_seconds("controller.passage_time", passage_time)?;
+                }
+                if phase.min_green > phase.max_green {
+                    return Err(invalid("controller.min_green", "must not exceed max_green"));
+                }
+                phase.recall = match recall {
+                    Some(RecallName::Off) | None => Recall::Off,
+                    Some(RecallName::Minimum) => Recall::Minimum,
+                    Some(RecallName::Maximum) => Recall::Maximum,
+                };
+                ControllerKind::Actuated(ActuatedController::new(vec![phase; phase_count]))
+            },
+            ControllerSection::MaxPressure { interval } => {
+                let interval = match interval {
+                    Some(interval) => non_negative_seconds("controller.interval", interval)?,
+                    None => Duration::from_millis(500),
+                };
+                ControllerKind::MaxPressure(MaxPressureController::new(interval))
+            },
+            ControllerSection::Webster { saturation_flow, offset } => {
+                let saturation_flow = saturation_flow.unwrap_or(DEFAULT_SATURATION_FLOW);
+                if !(saturation_flow.is_finite() && saturation_flow > 0.0) {
+                    return Err(invalid("controller.saturation_flow", "must be positive"));
+                }
+                let offset = non_negative_seconds("controller.offset", offset)?;
+                let flows = demand.entrance_flows(network);
+                ControllerKind::FixedTime(FixedTimeController::webster_for_plan(plan, &flows, saturation_flow, offset, timings.yellow + timings.all_red))
+            },
+        };
+
+        Ok(controller)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Field named by the validation error for a TOML scenario
+    fn invalid_field(text: &str) -> String {
+        match parse(text, ScenarioFormat::Toml) {
+            Err(ScenarioError::Invalid { field, .. }) => field,
+            Err(err) => panic!("expected a validation error, got: {}", err),
+            Ok(_) => panic!("expected a validation error for:\n{}", text),
+        }
+    }
+
+    #[test]
+    fn empty_file_is_the_built_in_scenario() {
+        let (config, runs) = parse("", ScenarioFormat::Toml).unwrap();
+        assert_eq!(runs, 1);
+        assert_eq!(config.scenario.network.nodes.len(), 1);
+        assert_eq!(config.scenario.vehicle_types.len(), 1);
+    }
+
+    #[test]
+    fn json_reads_like_toml() {
+        let (config, runs) = parse(r#"{ "run": { "runs": 3, "steps": 10 }, "network": { "columns": 2 } }"#, ScenarioFormat::Json).unwrap();
+        assert_eq!(runs, 3);
+        assert!(matches!(config.stop, StopCondition::Steps(10)));
+        assert_eq!(config.scenario.network.nodes.len(), 2);
+    }
+
+    #[test]
+    fn detectors_go_at_one_node_or_every_node() {
+        let text = "[network]\ncolumns = 2\n[[detectors]]\nkind = \"loop\"\nentrance = 1\nsetback = 40.0\n\
+                    [[detectors]]\nnode = 1\nkind = \"area\"\nentrance = 6\nlength = 60";
+        let (config, _) = parse(text, ScenarioFormat::Toml).unwrap();
+        let detectors = config.scenario.detectors.unwrap();
+        let stop_bar = DetectorPlacement { setback: 40.0, ..DetectorPlacement::new(DetectorKind::Loop, 1) };
+        let queue = DetectorPlacement { length: 60, ..DetectorPlacement::new(DetectorKind::Area, 6) };
+        assert_eq!(detectors, vec![vec![stop_bar], vec![stop_bar, queue]]);
+        assert!(parse("", ScenarioFormat::Toml).unwrap().0.scenario.detectors.is_none());
+    }
+
+    #[test]
+    fn unknown_fields_do_not_parse() {
+        assert!(matches!(parse("[run]\nspeed = 2.0", ScenarioFormat::Toml), Err(ScenarioError::Parse(_))));
+        assert!(matches!(parse("[[vehicle_types]]\nlength = 10\nwidth = 10\n[vehicle_types.idm]\nreaction_time = 1.0", ScenarioFormat::Toml),
+            Err(ScenarioError::Parse(_))));
+    }
+
+    #[test]
+    fn validation_names_the_offending_field() {
+        assert_eq!(invalid_field("[run]\nduration = 5.0\nsteps = 10"), "run");
+        assert_eq!(invalid_field("[run]\ndt = 0.0"), "run.dt");
+        assert_eq!(invalid_field("[run]\nruns = 0"), "run.runs");
+        assert_eq!(invalid_field("[geometry]\nlane_width = -1.0"), "geometry.lane_width");
+        assert_eq!(invalid_field("[network]\ncolumns = 0"), "network");
+        assert_eq!(invalid_field("[network]\ncolumns = 2\nspacing = 10.0"), "network.spacing");
+        assert_eq!(invalid_field("[demand]\nflows = [100.0, -1.0]"), "demand.flows");
+        assert_eq!(invalid_field("[[demand.profile]]\nduration = 0.0\nfactor = 1.0"), "demand.profile[0].duration");
+        assert_eq!(invalid_field("vehicle_types = []"), "vehicle_types");
+        assert_eq!(invalid_field("[[vehicle_types]]\nlength = 10\nwidth = 10\nmodel = \"bicycle\""), "vehicle_types[0].model");
+        assert_eq!(invalid_field("[[vehicle_types]]\nlength = 10\nwidth = 10\nmodel = \"krauss\"\n[vehicle_types.krauss]\nimperfection = 2.0"),
+            "vehicle_types[0].krauss.imperfection");
+        assert_eq!(invalid_field("[[vehicle_types]]\nlength = 10\nwidth = 10\nmodel = \"gipps\"\n[vehicle_types.idm]\ntime_headway = 1.0"),
+            "vehicle_types[0].idm");
+        assert_eq!(invalid_field("[pedestrians]\nwalking_speed = 0.0"), "pedestrians.walking_speed");
+        assert_eq!(invalid_field("[[detectors]]\nkind = \"loop\"\nentrance = 8"), "detectors[0].entrance");
+        assert_eq!(invalid_field("[[detectors]]\nnode = 1\nkind = \"loop\"\nentrance = 0"), "detectors[0].node");
+        assert_eq!(invalid_field("[[detectors]]\nkind = \"area\"\nentrance = 0\nlength = 0"), "detectors[0].length");
+        assert_eq!(invalid_field("[signals]\nplan = \"four-way-stop\""), "signals.plan");
+        assert_eq!(invalid_field("[controller]\nkind = \"fixed\"\ncycle = 4.0\nsplits = [1.0]"), "controller.splits");
+        assert_eq!(invalid_field("[controller]\nkind = \"fixed\"\ncycle = 0.5"), "controller.cycle");
+        assert_eq!(invalid_field("[controller]\nkind = \"webster\"\nsaturation_flow = 0.0"), "controller.saturation_flow");
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::time::Duration;
+use rand::RngCore;
+use crate::intersection_manager::IntersectionManager;
+use crate::fixed_time_controller::FixedTimeController;
+use crate::qlearning::QLearningController;
+use crate::actuated_controller::ActuatedController;
+use crate::max_pressure_controller::MaxPressureController;
+use crate::detector::Detector;
+use crate::vehicle::TURN_DIRECTIONS;
+
+// What a controller gets to see at each decision step
+pub struct Observation<'a> {
+    pub now: Duration,
+    pub intersection: &'a IntersectionManager,
+    pub detectors: &'a [Detector],
+    pub queues: [usize; 8], // Per entrance: waiting to enter plus stopped short of the box
+    pub turn_queues: [[usize; TURN_DIRECTIONS.len()]; 8], // queues split by the turn each vehicle makes, in TURN_DIRECTIONS order
+    pub exit_queues: [usize; 4], // Per exit leg heading: stopped after leaving the box
+}
+
+// One node's queues, counted by Simulation::queue_counts and fields as in Observation
+#[derive(Clone, Copy, Default)]
+pub struct QueueCounts {
+    pub queues: [usize; 8],
+    pub turn_queues: [[usize; TURN_DIRECTIONS.len()]; 8],
+    pub exit_queues: [usize; 4],
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SignalRequest {
+    Hold,
+    Phase(usize), // Serve this phase of the intersection's signal plan
+    Approach { approach: usize, green: bool },
+}
+
+// Controllers only request; StopLight and IntersectionManager enforce clearance and conflicts
+pub trait SignalController: std::any::Any + Send {
+    // How often decide is called; zero means every simulation step
+    fn decision_interval(&self) -> Duration;
+
+    fn decide(&mut self, observation: &Observation, rng: &mut dyn RngCore) -> SignalRequest;
+}
+
+#[derive(Clone)]
+pub enum ControllerKind {
+    QLearning,
+    FixedTime(FixedTimeController),
+    Actuated(ActuatedController),
+    MaxPressure(MaxPressureController),
+    Custom(Arc<dyn Fn() -> Box<dyn SignalController> + Send + Sync>),
+}
+
+impl ControllerKind {
+    pub fn build(&self) -> Box<dyn SignalController> {
+        match self {
+            ControllerKind::QLearning => Box::new(QLearningController::new()),
+            ControllerKind::FixedTime(controller) => Box::new(controller.clone()),
+            ControllerKind::Actuated(controller) => Box::new(controller.clone()),
+            ControllerKind::MaxPressure(controller) => Box::new(controller.clone()),
+            ControllerKind::Custom(factory) => factory(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::any::Any;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::geometry::Geometry;
+    use crate::intersection::Intersection;
+    use crate::scenario::Scenario;
+    use crate::signal_plan::SignalHead;
+
+    // Asks for north-south every decision and counts the decisions
+    struct NorthSouth(Arc<AtomicUsize>);
+
+    impl SignalController for NorthSouth {
+        fn decision_interval(&self) -> Duration {
+            Duration::from_millis(100)
+        }
+
+        fn decide(&mut self, observation: &Observation, _rng: &mut dyn RngCore) -> SignalRequest {
+            assert_eq!(observation.detectors.len(), 16);
+            self.0.fetch_add(1, Ordering::Relaxed);
+            SignalRequest::Phase(1)
+        }
+    }
+
+    #[test]
+    fn kinds_build_their_own_controller() {
+        let is = |kind: ControllerKind| -> Box<dyn Any> { kind.build() };
+        assert!(is(ControllerKind::QLearning).is::<QLearningController>());
+        let fixed = FixedTimeController::new(Duration::from_secs(4), &[1.0, 1.0], Duration::ZERO, Duration::from_millis(500));
+        assert!(is(ControllerKind::FixedTime(fixed)).is::<FixedTimeController>());
+        assert!(is(ControllerKind::Actuated(ActuatedController::new(Vec::new()))).is::<ActuatedController>());
+        assert!(is(ControllerKind::MaxPressure(MaxPressureController::new(Duration::ZERO))).is::<MaxPressureController>());
+    }
+
+    #[test]
+    fn intersections_ask_at_the_decision_interval_and_apply_the_request() {
+        let decisions = Arc::new(AtomicUsize::new(0));
+        let counter = decisions.clone();
+        let kind = ControllerKind::Custom(Arc::new(move || Box::new(NorthSouth(counter.clone()))));
+        let mut intersection = Intersection::new(0, &Geometry::default(), &Scenario::default(), kind.build());
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let dt = Duration::from_millis(10);
+        for step in 1..=100 {
+            intersection.update(dt * step, dt, &[], QueueCounts::default(), &mut rng);
+        }
+        assert_eq!(decisions.load(Ordering::Relaxed), 10);
+        for approach in 0..4 {
+            assert_eq!(intersection.manager.head(SignalHead::Through(approach)).is_green(), approach % 2 == 1);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::fmt;
+use crate::geometry::Geometry;
+use crate::vehicle::{TurnDirection, Vehicle};
+
+pub const APPROACHES: usize = 4;
+// Signalised turns; U-turns share the inside lane and its signal with the left turn
+pub const TURNS: [TurnDirection; 3] = [TurnDirection::Left, TurnDirection::Straight, TurnDirection::Right];
+const MOVEMENT_COUNT: usize = APPROACHES * TURNS.len();
+
+// Two swept paths closer than this at any point are treated as conflicting
+const CONFLICT_DISTANCE: f64 = 10.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Movement {
+    pub approach: usize,
+    pub turn: TurnDirection,
+}
+
+impl Movement {
+    fn index(&self) -> usize {
+        self.approach * TURNS.len() + TURNS.iter().position(|turn| *turn == self.turn).unwrap()
+    }
+
+    // Entrances (see Vehicle::new) whose lanes carry this movement
+    pub fn entrances(&self) -> Vec<u32> {
+        let left_lane = self.approach as u32 * 2;
+        match self.turn {
+            TurnDirection::Left | TurnDirection::UTurn => vec![left_lane],
+            TurnDirection::Straight => vec![left_lane, left_lane + 1],
+            TurnDirection::Right => vec![left_lane + 1],
+        }
+    }
+
+    // Quarter-turn heading of the leg the movement leaves on, matching Vehicle::exit_heading
+    pub fn exit_heading(&self) -> usize {
+        match self.turn {
+            TurnDirection::Left => (self.approach + 3) % APPROACHES,
+            TurnDirection::Straight => self.approach,
+            TurnDirection::Right => (self.approach + 1) % APPROACHES,
+            TurnDirection::UTurn => (self.approach + 2) % APPROACHES,
+        }
+    }
+
+    fn opposes(&self, other: &Movement) -> bool {
+        (self.approach + 2) % APPROACHES == other.approach
+    }
+}
+
+impl fmt::Display for Movement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "approach {} {:?}", self.approach, self.turn)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Conflict {
+    None,
+    // A left turn crossing the opposing approach; allowed in one phase if the left turn yields
+    Permissive,
+    Hard,
+}
+
+pub struct ConflictMatrix {
+    conflicts: [[Conflict; MOVEMENT_COUNT]; MOVEMENT_COUNT],
+}
+
+impl ConflictMatrix {
+    // Traces every movement through the box with the vehicle kinematics and compares the swept paths
+    pub fn from_geometry(geometry: &Geometry) -> Self {
+        let movements = all_movements();
+        let paths: Vec<Vec<(f64, f64)>> = movements.iter().map(|movement| {
+            movement.entrances().into_iter()
+                .flat_map(|entrance| Vehicle::movement_path(geometry, entrance, movement.turn))
+                .collect()
+        }).collect();
+
+        let mut conflicts = [[Conflict::None; MOVEMENT_COUNT]; MOVEMENT_COUNT];
+        for (i, a) in movements.iter().enumerate() {
+            for (j, b) in movements.iter().enumerate() {
+                if a.approach == b.approach || !paths_meet(&paths[i], &paths[j]) {
+                    continue;
+                }
+                let left_across_opposing = (a.turn == TurnDirection::Left || b.turn == TurnDirection::Left) && a.opposes(b);
+                conflicts[i][j] = if left_across_opposing { Conflict::Permissive } else { Conflict::Hard };
+            }
+        }
+
+        Self { conflicts }
+    }
+
+    pub fn between(&self, a: &Movement, b: &Movement) -> Conflict {
+        self.conflicts[a.index()][b.index()]
+    }
+}
+
+fn all_movements() -> Vec<Movement> {
+    (0..APPROACHES).flat_map(|approach| TURNS.iter().map(move |&turn| Movement { approach, turn })).collect()
+}
+
+fn paths_meet(a: &[(f64, f64)], b: &[(f64, f64)]) -> bool {
+    a.iter().any(|&(ax, ay)| b.iter().any(|&(bx, by)| (ax - bx).hypot(ay - by) < CONFLICT_DISTANCE))
+}
+
+// Every approach has a head for through and right-turning traffic and a left-turn head, which
+// U-turns obey as well
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SignalHead {
+    Through(usize),
+    LeftTurn(usize),
+}
+
+impl SignalHead {
+    pub fn for_movement(movement: &Movement) -> Self {
+        match movement.turn {
+            TurnDirection::Left | TurnDirection::UTurn => SignalHead::LeftTurn(movement.approach),
+            TurnDirection::Straight | TurnDirection::Right => SignalHead::Through(movement.approach),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Protection {
+    Protected,
+    Permitted, // Green, but must yield to conflicting movements that are also green
+}
+
+#[derive(Clone)]
+pub struct Phase {
+    pub name: String,
+    pub movements: Vec<(Movement, Protection)>,
+}
+
+impl Phase {
+    pub fn serves(&self, approach: usize) -> bool {
+        self.movements.iter().any(|(movement, _)| movement.approach == approach)
+    }
+
+    pub fn serves_head(&self, head: SignalHead) -> bool {
+        self.movements.iter().any(|(movement, _)| SignalHead::for_movement(movement) == head)
+    }
+
+    // How the phase runs an approach's left turn, if it does
+    pub fn left_turn_protection(&self, approach: usize) -> Option<Protection> {
+        self.movements.iter()
+            .find(|(movement, _)| movement.approach == approach && movement.turn == TurnDirection::Left)
+            .map(|&(_, protection)| protection)
+    }
+}
+
+#[derive(Debug)]
+pub enum PlanError {
+    Empty,
+    UnknownApproach { phase: String, approach: usize },
+    PermittedThrough { phase: String, movement: Movement },
+    Conflict { phase: String, first: Movement, second: Movement },
+}
+
+impl fmt::Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlanError::Empty => write!(f, "signal plan has no phases"),
+            PlanError::UnknownApproach { phase, approach } =>
+                write!(f, "phase '{}' refers to approach {}, but the intersection has {}", phase, approach, APPROACHES),
+            PlanError::PermittedThrough { phase, movement } =>
+                write!(f, "phase '{}' makes {} permitted, but only turns can yield", phase, movement),
+            PlanError::Conflict { phase, first, second } =>
+                write!(f, "phase '{}' gives conflicting movements green together: {} and {}", phase, first, second),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+// Phases are served in order and wrap around
+#[derive(Clone)]
+pub struct SignalPlan {
+    pub phases: Vec<Phase>,
+}
+
+impl SignalPlan {
+    // East-west then north-south, with left turns permitted across the opposing through traffic
+    pub fn two_phase() -> Self {
+        Self {
+            phases: vec![
+                Phase { name: "east-west".to_string(), movements: approach_movements(&[0, 2]) },
+                Phase { name: "north-south".to_string(), movements: approach_movements(&[1, 3]) },
+            ],
+        }
+    }
+
+    // Leading protected lefts for each pair of opposing approaches, then their through traffic with
+    // the lefts permitted
+    pub fn protected_left() -> Self {
+        let mut phases = Vec::new();
+        for (name, approaches) in [("east-west", [0, 2]), ("north-south", [1, 3])] {
+            phases.push(Phase {
+                name: format!("{} left", name),
+                movements: approaches.iter().map(|&approach| (Movement { approach, turn: TurnDirection::Left }, Protection::Protected)).collect(),
+            });
+            phases.push(Phase { name: name.to_string(), movements: approach_movements(&approaches) });
+        }
+        Self { phases }
+    }
+
+    // Each approach on its own, so every movement is protected
+    pub fn split_phase() -> Self {
+        Self {
+            phases: (0..APPROACHES).map(|approach| Phase {
+                name: format!("approach {}", approach),
+                movements: TURNS.iter().map(|&turn| (Movement { approach, turn }, Protection::Protected)).collect(),
+            }).collect(),
+        }
+    }
+
+    pub fn validate(&self, conflicts: &ConflictMatrix) -> Result<(), PlanError> {
+        if self.phases.is_empty() {
+            return Err(PlanError::Empty);
+        }
+
+        for phase in &self.phases {
+            for &(movement, protection) in &phase.movements {
+                if movement.approach >= APPROACHES {
+                    return Err(PlanError::UnknownApproach { phase: phase.name.clone(), approach: movement.approach });
+                }
+                if protection == Protection::Permitted && movement.turn == TurnDirection::Straight {
+                    return Err(PlanError::PermittedThrough { phase: phase.name.clone(), movement });
+                }
+            }
+
+            for (i, &(first, first_protection)) in phase.movements.iter().enumerate() {
+                for &(second, second_protection) in &phase.movements[i + 1..] {
+                    let allowed = match conflicts.between(&first, &second) {
+                        Conflict::None => true,
+                        Conflict::Permissive => {
+                            let left_protection = if first.turn == TurnDirection::Left { first_protection } else { second_protection };
+                            left_protection == Protection::Permitted
+                        },
+                        Conflict::Hard => false,
+                    };
+                    if !allowed {
+                        return Err(PlanError::Conflict { phase: phase.name.clone(), first, second });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Two heads may show green together only if some phase of the plan serves both
+    pub fn may_share_green(&self, first: SignalHead, second: SignalHead) -> bool {
+        self.phases.iter().any(|phase| phase.serves_head(first) && phase.serves_head(second))
+    }
+}
+
+fn approach_movements(approaches: &[usize]) -> Vec<(Movement, Protection)> {
+    approaches.iter().flat_map(|&approach| TURNS.iter().map(move |&turn| {
+        let protection = if turn == TurnDirection::Left { Protection::Permitted } else { Protection::Protected };
+        (Movement { approach, turn }, protection)
+    })).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movement(approach: usize, turn: TurnDirection) -> Movement {
+        Movement { approach, turn }
+    }
+
+    fn phase(movements: Vec<(Movement, Protection)>) -> SignalPlan {
+        SignalPlan { phases: vec![Phase { name: "test".to_string(), movements }] }
+    }
+
+    #[test]
+    fn conflict_matrix_classifies_crossing_paths() {
+        let conflicts = ConflictMatrix::from_geometry(&Geometry::default());
+        let (left, straight) = (TurnDirection::Left, TurnDirection::Straight);
+
+        assert_eq!(conflicts.between(&movement(0, left), &movement(2, straight)), Conflict::Permissive);
+        assert_eq!(conflicts.between(&movement(2, straight), &movement(0, left)), Conflict::Permissive);
+        assert_eq!(conflicts.between(&movement(0, straight), &movement(1, straight)), Conflict::Hard);
+        assert_eq!(conflicts.between(&movement(0, straight), &movement(2, straight)), Conflict::None);
+        assert_eq!(conflicts.between(&movement(0, left), &movement(0, straight)), Conflict::None);
+    }
+
+    #[test]
+    fn built_in_plans_are_conflict_free() {
+        let conflicts = ConflictMatrix::from_geometry(&Geometry::default());
+        for plan in [SignalPlan::two_phase(), SignalPlan::protected_left(), SignalPlan::split_phase()] {
+            assert!(plan.validate(&conflicts).is_ok());
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_plans() {
+        let conflicts = ConflictMatrix::from_geometry(&Geometry::default());
+        let (left, straight) = (TurnDirection::Left, TurnDirection::Straight);
+
+        assert!(matches!(SignalPlan { phases: Vec::new() }.validate(&conflicts), Err(PlanError::Empty)));
+        assert!(matches!(phase(vec![(movement(4, straight), Protection::Protected)]).validate(&conflicts),
+            Err(PlanError::UnknownApproach { approach: 4, .. })));
+        assert!(matches!(phase(vec![(movement(0, straight), Protection::Permitted)]).validate(&conflicts),
+            Err(PlanError::PermittedThrough { .. })));
+        assert!(matches!(phase(vec![(movement(0, straight), Protection::Protected), (movement(1, straight), Protection::Protected)]).validate(&conflicts),
+            Err(PlanError::Conflict { .. })));
+        // A left across opposing through traffic only runs with it if it yields
+        assert!(matches!(phase(vec![(movement(0, left), Protection::Protected), (movement(2, straight), Protection::Protected)]).validate(&conflicts),
+            Err(PlanError::Conflict { .. })));
+        assert!(phase(vec![(movement(0, left), Protection::Permitted), (movement(2, straight), Protection::Protected)]).validate(&conflicts).is_ok());
+    }
+
+    #[test]
+    fn protected_left_plan_leads_with_protected_lefts() {
+        let plan = SignalPlan::protected_left();
+        assert_eq!(plan.phases[0].left_turn_protection(0), Some(Protection::Protected));
+        assert_eq!(plan.phases[1].left_turn_protection(0), Some(Protection::Permitted));
+        assert_eq!(plan.phases[0].left_turn_protection(1), None);
+        assert!(plan.may_share_green(SignalHead::LeftTurn(0), SignalHead::LeftTurn(2)));
+        assert!(!plan.may_share_green(SignalHead::Through(0), SignalHead::Through(1)));
+    }
+}
@@ -1,70 +1,133 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::distributions::{Distribution, WeightedIndex};
+use crate::arrivals::{ArrivalGenerator, ArrivalProcess};
 use winit::window::Window;
 use crate::collision::rectangles_intersect;
-use crate::config::{DASH_LENGTH, GAP_LENGTH, HEIGHT, WIDTH};
+use crate::config::{DASH_LENGTH, GAP_LENGTH, VISION_LENGTH};
+use crate::scenario::{Demand, Scenario};
 use pixels::{Pixels, SurfaceTexture};
 use winit::event_loop::{ActiveEventLoop, ControlFlow};
-use crate::vehicle::Vehicle;
-use std::time::{Duration, Instant};
-use crate::intersection_manager::IntersectionManager;
-use crate::qlearning::QLearning;
-
-pub struct Simulation {
+use crate::vehicle::{random_turn, Vehicle, VehicleType, TURN_DIRECTIONS};
+use crate::car_following::{Leader, LeaderKind};
+use crate::stop_light::SignalState;
+use std::time::Duration;
+use crate::clock::SimClock;
+use crate::network::{entrance_for, lane_of, Network};
+use crate::routing::RouteTable;
+use crate::turning::TurningRatios;
+use crate::gap_acceptance::GapAcceptance;
+use crate::pedestrian::{Pedestrian, PedestrianSettings};
+use crate::signal_plan::{Movement, APPROACHES};
+use crate::intersection::Intersection;
+use crate::signal_controller::{ControllerKind, QueueCounts};
+use crate::trip::TripRecord;
+
+pub struct Simulation<R: Rng = StdRng> {
     pixels: Option<Pixels>,
     vehicles: Vec<Vehicle>,
+    pub vehicle_types: Vec<VehicleType>,
+    pub network: Network,
+    pub demand: Demand,
     window_width: u32,
     window_height: u32,
     background: Option<Vec<u8>>,
-    time_since_last_spawn: Instant,
+    pub clock: SimClock,
+    last_spawn: Duration,
     id_counter: usize,
-    pub intersection_manager: IntersectionManager,
-    release_queue: [Vec<Vehicle>; 8],
-    pub qlearning: QLearning,
-    last_light_change: Instant,
-    min_volume: f64,
-    max_volume: f64,
+    pub intersections: Vec<Intersection>, // One per network node, in node order
+    pub completed_trips: Vec<TripRecord>,
+    release_queue: Vec<[Vec<Vehicle>; 8]>, // Per node; only entrances fed from the map boundary are used
+    boundary_entrances: Vec<(usize, u32)>,
+    routes: Option<RouteTable>, // Only with OD demand
+    turning: Option<TurningRatios>,
+    gap_acceptance: GapAcceptance,
+    arrivals: Vec<ArrivalGenerator>, // Per OD origin zone, or else per boundary entrance with flows
+    pedestrians: Vec<Pedestrian>,
+    pedestrian_settings: PedestrianSettings,
+    pedestrian_arrivals: Vec<ArrivalGenerator>, // Per node and leg, when pedestrians have a flow
+    pedestrian_counter: usize,
+    rng: R,
 }
 
-impl Simulation {
-    pub fn new(window: Option<&Window>) -> Self {
+impl<R: Rng + SeedableRng> Simulation<R> {
+    // Every stochastic decision draws from the seeded rng, so a seed fully determines a run.
+    // Each intersection gets its own controller built from controller.
+    pub fn new(window: Option<&Window>, scenario: &Scenario, seed: u64, controller: &ControllerKind) -> Self {
+        let network = scenario.network.clone();
+        let geometry = network.geometry;
         let (pixels, background, window_width, window_height) = if let Some(window) = window {
             let window_size = window.inner_size();
             let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, window);
-            let mut pixels = Pixels::new(WIDTH, HEIGHT, surface_texture)
+            let mut pixels = Pixels::new(geometry.width, geometry.height, surface_texture)
                 .expect("Failed to create pixels");
             let frame = pixels.frame_mut();
-            let background = Some(load_background_frame(frame));
+            let background = Some(load_background_frame(frame, &network));
 
             (Some(pixels), background, window_size.width, window_size.height)
         } else {
-            (None, None, WIDTH, HEIGHT)
+            (None, None, geometry.width, geometry.height)
         };
 
         let vehicles: Vec<Vehicle> = Vec::new();
-        let intersection_manager = IntersectionManager::new();
+        let mut rng = R::seed_from_u64(seed);
+        let demand = &scenario.demand;
+        let routes = demand.od.as_ref().map(|_| RouteTable::new(&network));
+        let source_flows: Vec<f64> = match (&demand.od, &demand.flows) {
+            (Some(od), _) => (0..od.zones()).map(|origin| od.origin_flow(origin)).collect(),
+            (None, Some(flows)) => flows.clone(),
+            (None, None) => Vec::new(),
+        };
+        let arrivals = source_flows.into_iter()
+            .map(|flow| ArrivalGenerator::new(demand.arrivals, flow, &demand.profile, &mut rng))
+            .collect();
+        let pedestrian_arrivals = if scenario.pedestrians.flow > 0.0 {
+            (0..network.nodes.len() * APPROACHES)
+                .map(|_| ArrivalGenerator::new(ArrivalProcess::Poisson, scenario.pedestrians.flow, &demand.profile, &mut rng))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let intersections = (0..network.nodes.len())
+            .map(|node| Intersection::new(node, &network.node_geometry(node), scenario, controller.build()))
+            .collect();
 
         Self {
             pixels,
             vehicles,
+            vehicle_types: scenario.vehicle_types.clone(),
+            demand: scenario.demand.clone(),
             window_width,
             window_height,
             background,
-            time_since_last_spawn: Instant::now(),
+            clock: SimClock::new(),
+            last_spawn: Duration::ZERO,
             id_counter: 0,
-            intersection_manager,
-            release_queue: [Vec::new(), Vec::new(), Vec::new(), Vec::new(),
-                            Vec::new(), Vec::new(), Vec::new(), Vec::new()],
-            qlearning: QLearning::new(9, 5),
-            last_light_change: Instant::now(),
-            min_volume: f64::MAX,
-            max_volume: f64::MIN,
+            intersections,
+            completed_trips: Vec::new(),
+            release_queue: (0..network.nodes.len()).map(|_| Default::default()).collect(),
+            boundary_entrances: network.boundary_entrances(),
+            routes,
+            turning: scenario.turning.clone(),
+            gap_acceptance: scenario.gap_acceptance,
+            arrivals,
+            pedestrians: Vec::new(),
+            pedestrian_settings: scenario.pedestrians,
+            pedestrian_arrivals,
+            pedestrian_counter: 0,
+            network,
+            rng,
         }
     }
 
     pub fn update(&mut self, dt: Duration) {
-        for queue in &mut self.release_queue {
+        self.clock.advance(dt);
+        let now = self.clock.now();
+
+        for queue in self.release_queue.iter_mut().flatten() {
             if !queue.is_empty() {
-                let v1 = queue.swap_remove(0);
+                let mut v1 = queue.swap_remove(0);
                 let mut passed = true;
 
                 for v2 in &self.vehicles {
@@ -75,6 +138,7 @@ impl Simulation {
                 }
 
                 if passed {
+                    v1.trip.entered_at = Some(now);
                     self.vehicles.push(v1);
                 } else {
                     queue.push(v1);
@@ -82,119 +146,323 @@ impl Simulation {
             }
         }
 
-        for vehicle in &mut self.vehicles {
-            vehicle.update(dt);
+        let leaders = self.find_leaders();
+        for (vehicle, leader) in self.vehicles.iter_mut().zip(&leaders) {
+            vehicle.update(dt, leader.as_ref(), &mut self.rng);
+            if vehicle.is_stopped() && leader.as_ref().is_some_and(|leader| leader.kind == LeaderKind::RedSignal) {
+                vehicle.stopped_at_line = true;
+            }
+        }
+
+        // Vehicles that have cleared a box carry on to the next intersection along their heading. One
+        // that needs the other lane there waits until that lane is clear alongside, or until it comes
+        // within sight of the next stop line, when it changes lane regardless and may overlap. The node
+        // it moves on from records its part of the trip.
+        for index in 0..self.vehicles.len() {
+            let vehicle = &self.vehicles[index];
+            let Some(heading) = vehicle.exit_heading() else { continue };
+            let Some(next) = self.network.next_node(vehicle.node, heading) else { continue };
+            let (lane, turn) = match (vehicle.next_step, vehicle.next_route_step(), &self.turning) {
+                (Some(step), _, _) | (None, Some(step), _) => step,
+                (None, None, Some(turning)) => turning.choose(&self.network, next, heading, vehicle.lane, &mut self.rng),
+                (None, None, None) => (vehicle.lane, random_turn(vehicle.lane, &mut self.rng)),
+            };
+            let geometry = self.network.node_geometry(next);
+            if lane != vehicle.lane {
+                let (center_x, center_y) = geometry.center();
+                let to_next_box = vehicle.distance_ahead(center_x, center_y) - geometry.box_size / 2.0;
+                let in_sight = to_next_box < geometry.stop_line_setback + VISION_LENGTH as f64;
+                let target = vehicle.lane_change_bounds(&geometry, lane);
+                let occupied = self.vehicles.iter().any(|other| other.id != vehicle.id && rectangles_intersect(&target, &other.bounds));
+                if occupied && !in_sight {
+                    self.vehicles[index].next_step = Some((lane, turn));
+                    continue;
+                }
+            }
+            let segment = self.vehicles[index].segment_record(now);
+            self.intersections[segment.node].metrics.record_trip(&segment);
+            self.intersections[segment.node].manager.intersection_volume[(segment.entrance / 2) as usize] -= 1;
+            let vehicle = &mut self.vehicles[index];
+            vehicle.enter_node(next, &geometry, lane, turn, now);
+            self.intersections[next].manager.intersection_volume[(vehicle.entrance / 2) as usize] += 1;
         }
 
         self.vehicles.retain(|vehicle| {
             if !vehicle.check_bounds() {
-                return true;
+                true
             } else {
-                self.intersection_manager.intersection_volume[(vehicle.entrance / 2) as usize] -= 1;
-                return false;
+                // The last node's metrics get the vehicle's part of the trip there; the whole trip is
+                // kept for the network
+                self.intersections[vehicle.node].manager.intersection_volume[(vehicle.entrance / 2) as usize] -= 1;
+                self.intersections[vehicle.node].metrics.record_trip(&vehicle.segment_record(now));
+                self.completed_trips.push(vehicle.trip_record(now));
+                false
             }
         });
-        self.spawn_on_timer(0.02);
-        self.handle_vehicle_collisions();
-        self.handle_vehicle_stops();
-
-        for stop_light in &mut self.intersection_manager.stop_lights {
-            stop_light.update();
+        if self.demand.od.is_some() {
+            self.spawn_od_trips();
+        } else if self.demand.flows.is_some() {
+            self.spawn_arrivals();
+        } else {
+            self.spawn_on_timer(self.demand.spawn_interval);
         }
+        self.update_pedestrians(dt);
 
-        if self.last_light_change.elapsed() >= Duration::from_millis(100) {
-            let state = self.intersection_manager.get_state();
-            let action = self.qlearning.choose_action(&state);
-            self.intersection_manager.update_from_action(action);
-            let next_state = self.intersection_manager.get_state();
-            let total_volume: u32 = self.intersection_manager.intersection_volume.iter().sum();
-            let reward = self.calculate_reward(total_volume);
-            self.qlearning.update(&state, action, reward, &next_state);
-            self.last_light_change = Instant::now();
+        let queue_counts = self.queue_counts();
+        for (intersection, counts) in self.intersections.iter_mut().zip(queue_counts) {
+            intersection.update(now, dt, &self.vehicles, counts, &mut self.rng);
         }
     }
 
-    fn calculate_reward(&mut self, total_volume: u32) -> f64 {
-        let total_volume = total_volume as f64;
+    pub fn vehicles(&self) -> &[Vehicle] {
+        &self.vehicles
+    }
+
+    pub fn pedestrians(&self) -> &[Pedestrian] {
+        &self.pedestrians
+    }
 
-        // Update min and max volumes observed
-        if total_volume < self.min_volume {
-            self.min_volume = total_volume;
+    // Pedestrians arrive at either end of every crosswalk and press its button, cross on WALK and
+    // leave once on the far side
+    fn update_pedestrians(&mut self, dt: Duration) {
+        let now = self.clock.now();
+
+        for (index, generator) in self.pedestrian_arrivals.iter_mut().enumerate() {
+            let (node, leg) = (index / APPROACHES, index % APPROACHES);
+            for _ in 0..generator.arrivals_until(now, &self.demand.profile, &mut self.rng) {
+                self.intersections[node].manager.crosswalks[leg].press_button();
+                let from_right = self.rng.gen();
+                self.pedestrians.push(Pedestrian::new(self.pedestrian_counter, node, leg, from_right, self.pedestrian_settings.walking_speed, now));
+                self.pedestrian_counter += 1;
+            }
         }
-        if total_volume > self.max_volume {
-            self.max_volume = total_volume;
+
+        for pedestrian in &mut self.pedestrians {
+            pedestrian.update(now, dt, &self.intersections[pedestrian.node].manager.crosswalks[pedestrian.leg]);
         }
+        let intersections = &mut self.intersections;
+        self.pedestrians.retain(|pedestrian| {
+            let intersection = &mut intersections[pedestrian.node];
+            if !pedestrian.has_crossed(&intersection.manager.crosswalks[pedestrian.leg]) {
+                return true;
+            }
+            intersection.metrics.record_pedestrian(pedestrian.delay());
+            false
+        });
+    }
 
-        // Normalize volume
-        let normalized_volume = if self.max_volume != self.min_volume {
-            (total_volume - self.min_volume) / (self.max_volume - self.min_volume)
-        } else {
-            0.0
-        };
-        -normalized_volume
+    // Whether a pedestrian is on a crosswalk the vehicle would drive over: the one on its own leg,
+    // or the one on the leg it leaves by
+    fn pedestrians_in_path(&self, vehicle: &Vehicle) -> bool {
+        let approach = vehicle.entrance as usize / 2;
+        let exit_leg = (Movement { approach, turn: vehicle.turn }.exit_heading() + 2) % APPROACHES;
+        self.pedestrians.iter()
+            .any(|pedestrian| pedestrian.node == vehicle.node && pedestrian.is_crossing() && (pedestrian.leg == approach || pedestrian.leg == exit_leg))
     }
 
-    pub fn spawn_on_timer(&mut self, time: f32) {
-        let now = Instant::now();
-        let spawn_timer = now.duration_since(self.time_since_last_spawn);
+    pub fn spawn_on_timer(&mut self, interval: Duration) {
+        let now = self.clock.now();
+        let spawn_timer = self.clock.since(self.last_spawn);
 
-        if spawn_timer.as_secs_f32() > time {
-            let mut rng = rand::thread_rng();
-            let entrance = rng.gen_range(0..8);
-            let vehicle = Vehicle::new(self.id_counter, 50, 10, 10, entrance);
+        if spawn_timer > interval {
+            let (node, entrance) = *self.boundary_entrances.choose(&mut self.rng)
+                .expect("The network has at least one boundary entrance");
+            self.spawn_at(node, entrance, now);
+            self.last_spawn = now;
+        }
+    }
+
+    // Every boundary entrance releases vehicles at its flow, as timed by the arrival process and profile
+    pub fn spawn_arrivals(&mut self) {
+        let now = self.clock.now();
 
-            self.id_counter += 1;
+        let mut entrances = Vec::new();
+        for (generator, &entrance) in self.arrivals.iter_mut().zip(&self.boundary_entrances) {
+            let arrivals = generator.arrivals_until(now, &self.demand.profile, &mut self.rng);
+            entrances.extend(std::iter::repeat_n(entrance, arrivals));
+        }
 
-            self.intersection_manager.intersection_volume[(entrance / 2) as usize] += 1;
-            self.release_queue[entrance as usize].push(vehicle);
-            self.time_since_last_spawn = now;
+        for (node, entrance) in entrances {
+            self.spawn_at(node, entrance, now);
         }
     }
 
-    fn handle_vehicle_collisions(&mut self) {
-        for i in 0..self.vehicles.len() {
-            let (before, rest) = self.vehicles.split_at_mut(i);
-            let (v1, after) = rest.split_at_mut(1);
-            let v1 = &mut v1[0];
+    // A vehicle of a random type on a boundary entrance. With turning ratios its movement comes from
+    // its approach's shares and may move it to the approach's other lane.
+    fn spawn_at(&mut self, node: usize, entrance: u32, now: Duration) {
+        let vehicle_type = *self.vehicle_types.choose_weighted(&mut self.rng, |vehicle_type| vehicle_type.share)
+            .expect("At least one vehicle type with a positive share");
+        let geometry = self.network.node_geometry(node);
+        let vehicle = match &self.turning {
+            Some(turning) => {
+                let approach = entrance as usize / 2;
+                let (lane, turn) = turning.choose(&self.network, node, approach, lane_of(entrance), &mut self.rng);
+                Vehicle::spawned(self.id_counter, self.demand.initial_speed, &vehicle_type, &geometry, entrance_for(approach, lane), turn, now)
+            }
+            None => Vehicle::new(self.id_counter, self.demand.initial_speed, &vehicle_type, &geometry, entrance, now, &mut self.rng),
+        };
+        self.queue_for_release(vehicle.at_node(node));
+    }
 
-            for v2 in before.iter().chain(after.iter()) {
-                if rectangles_intersect(&v1.vision, &v2.bounds) {
-                    v1.speed = 0;
-                    break;
-                }
+    // Every origin zone releases its OD flow as timed by the arrival process and profile; each
+    // vehicle's destination is drawn in proportion to the flows from its origin and it follows a
+    // shortest route there
+    pub fn spawn_od_trips(&mut self) {
+        let Some(od) = &self.demand.od else { return };
+        let now = self.clock.now();
+
+        let mut trips = Vec::new();
+        for (origin, generator) in self.arrivals.iter_mut().enumerate() {
+            let arrivals = generator.arrivals_until(now, &self.demand.profile, &mut self.rng);
+            if arrivals == 0 {
+                continue;
+            }
+            let destinations = WeightedIndex::new(&od.flows[origin]).expect("OD flows are validated when loaded");
+            for _ in 0..arrivals {
+                trips.push((origin, destinations.sample(&mut self.rng)));
             }
         }
+
+        for (origin, destination) in trips {
+            let routes = self.routes.as_ref().expect("Routes are built with OD demand");
+            let route = routes.routes(origin, destination).choose(&mut self.rng)
+                .expect("OD pairs with trips have a route")
+                .clone();
+            let vehicle_type = *self.vehicle_types.choose_weighted(&mut self.rng, |vehicle_type| vehicle_type.share)
+                .expect("At least one vehicle type with a positive share");
+            let geometry = self.network.node_geometry(route.origin.node);
+            let vehicle = Vehicle::on_route(self.id_counter, self.demand.initial_speed, &vehicle_type, &geometry, route, now);
+            self.queue_for_release(vehicle);
+        }
+    }
+
+    fn queue_for_release(&mut self, vehicle: Vehicle) {
+        self.id_counter += 1;
+        self.intersections[vehicle.node].manager.intersection_volume[(vehicle.entrance / 2) as usize] += 1;
+        self.release_queue[vehicle.node][vehicle.entrance as usize].push(vehicle);
     }
 
-    fn handle_vehicle_stops(&mut self) {
-        for vehicle in &mut self.vehicles {
-            for stop_light in &self.intersection_manager.stop_lights {
-                if rectangles_intersect(&vehicle.vision, &stop_light.line) && !stop_light.active {
-                    vehicle.speed = 0;
-                    break;
+    // Per node: queued vehicles per entrance, per entrance and turn, and per exit leg, as in Observation.
+    // A vehicle queued on the link between two nodes is in the exit queue of the upstream one.
+    pub fn queue_counts(&self) -> Vec<QueueCounts> {
+        let mut counts = vec![QueueCounts::default(); self.intersections.len()];
+
+        let waiting = self.release_queue.iter().flatten().flatten();
+        let stopped = self.vehicles.iter().filter(|vehicle| vehicle.is_stopped());
+        for vehicle in waiting.chain(stopped) {
+            if vehicle.is_approaching() {
+                let approach = vehicle.entrance as usize / 2;
+                let turn = TURN_DIRECTIONS.iter().position(|&turn| turn == vehicle.turn).unwrap();
+                counts[vehicle.node].queues[vehicle.entrance as usize] += 1;
+                counts[vehicle.node].turn_queues[vehicle.entrance as usize][turn] += 1;
+                if let Some(previous) = self.network.previous_node(vehicle.node, approach) {
+                    counts[previous].exit_queues[approach] += 1;
                 }
+            } else if let Some(heading) = vehicle.exit_heading() {
+                counts[vehicle.node].exit_queues[heading] += 1;
             }
         }
+
+        counts
     }
 
-    pub fn draw(&mut self, event_loop: &ActiveEventLoop) {
+    // The closest vehicle or red stop line inside each vehicle's vision, as seen by the car-following model
+    fn find_leaders(&self) -> Vec<Option<Leader>> {
+        self.vehicles.iter().map(|vehicle| {
+            let mut leader: Option<Leader> = None;
+
+            for other in &self.vehicles {
+                if other.id == vehicle.id || !rectangles_intersect(&vehicle.vision, &other.bounds) {
+                    continue;
+                }
+                let distance = vehicle.distance_ahead(other.bounds.x, other.bounds.y);
+                if distance <= 0.0 {
+                    continue;
+                }
+                let gap = distance - (vehicle.bounds.width + other.bounds.width) as f64 / 2.0;
+                let speed = other.speed * (other.direction - vehicle.direction).cos();
+                keep_closest(&mut leader, Leader { gap, speed, kind: LeaderKind::Vehicle });
+            }
+
+            for intersection in &self.intersections {
+                if intersection.node == vehicle.node && self.gap_acceptance.may_turn_on_red(vehicle, &intersection.manager) {
+                    // Only has to give way, below
+                    continue;
+                }
+                // Left turns and U-turns obey their approach's left-turn head
+                let heads = if GapAcceptance::applies_to(vehicle) {
+                    &intersection.manager.left_turn_lights
+                } else {
+                    &intersection.manager.stop_lights
+                };
+                for stop_light in heads {
+                    let yellow = stop_light.state == SignalState::Yellow;
+                    if !(stop_light.requires_stop() || yellow) || !rectangles_intersect(&vehicle.vision, &stop_light.line) {
+                        continue;
+                    }
+                    // A vehicle whose front is already over the line carries on through the box
+                    let gap = vehicle.distance_ahead(stop_light.line.x, stop_light.line.y) - vehicle.bounds.width as f64 / 2.0;
+                    // On yellow only vehicles that can still stop comfortably do so
+                    let stopping_distance = vehicle.speed * vehicle.speed / (2.0 * vehicle.model.comfortable_deceleration());
+                    if gap > 0.0 && !(yellow && stopping_distance > gap) {
+                        keep_closest(&mut leader, Leader { gap, speed: 0.0, kind: LeaderKind::RedSignal });
+                    }
+                }
+            }
+
+            // Permitted lefts, U-turns and right turns on red give way to conflicting traffic at their
+            // line, and everyone gives way to pedestrians on the crosswalks ahead
+            let manager = &self.intersections[vehicle.node].manager;
+            let line = if GapAcceptance::applies_to(vehicle) {
+                &manager.left_turn_lights[vehicle.entrance as usize / 2].line
+            } else {
+                &manager.stop_lights[vehicle.entrance as usize / 2].line
+            };
+            if vehicle.is_approaching() && rectangles_intersect(&vehicle.vision, line) {
+                let gap = vehicle.distance_ahead(line.x, line.y) - vehicle.bounds.width as f64 / 2.0;
+                let gives_way = GapAcceptance::applies_to(vehicle) || self.gap_acceptance.may_turn_on_red(vehicle, manager);
+                if gap > 0.0 && (self.pedestrians_in_path(vehicle)
+                    || gives_way && self.gap_acceptance.must_yield(vehicle, &self.vehicles, manager, &self.network.node_geometry(vehicle.node))) {
+                    keep_closest(&mut leader, Leader { gap, speed: 0.0, kind: LeaderKind::Yield });
+                }
+            }
+
+            leader
+        }).collect()
+    }
+
+    pub fn draw(&mut self, event_loop: &ActiveEventLoop, alpha: f64) {
         if let Some(pixels) = &mut self.pixels {
             let frame = pixels.frame_mut();
 
             frame.copy_from_slice(self.background.as_ref().expect("Background should be set"));
 
             for vehicle in &self.vehicles {
-                vehicle.draw(frame, self.window_width, self.window_height);
+                vehicle.draw(frame, self.window_width, self.window_height, alpha);
+            }
+
+            for intersection in &self.intersections {
+                for detector in &intersection.detectors {
+                    detector.draw(frame, self.window_width, self.window_height);
+                }
+
+                for stop_light in intersection.manager.stop_lights.iter().chain(&intersection.manager.left_turn_lights) {
+                    stop_light.draw(frame, self.window_width, self.window_height);
+                }
+
+                for crosswalk in &intersection.manager.crosswalks {
+                    crosswalk.draw(frame, self.window_width, self.window_height);
+                }
             }
 
-            for stop_light in &self.intersection_manager.stop_lights {
-                stop_light.draw(frame, self.window_width, self.window_height);
+            for pedestrian in &self.pedestrians {
+                let crosswalk = &self.intersections[pedestrian.node].manager.crosswalks[pedestrian.leg];
+                pedestrian.draw(crosswalk, frame, self.window_width, self.window_height);
             }
 
             if let Err(err) = pixels.render() {
                 println!("Error during rendering: {:?}", err);
                 event_loop.set_control_flow(ControlFlow::Wait);
-                return;
             }
         } else {
             println!("No window context available for drawing");
@@ -202,23 +470,40 @@ impl Simulation {
     }
 }
 
-fn load_background_frame(frame: &mut [u8]) -> Vec<u8> {
+fn keep_closest(leader: &mut Option<Leader>, candidate: Leader) {
+    if leader.as_ref().is_none_or(|current| candidate.gap < current.gap) {
+        *leader = Some(candidate);
+    }
+}
+
+// Every column and row of the grid is a road across the whole map, crossing in the intersection boxes
+fn load_background_frame(frame: &mut [u8], network: &Network) -> Vec<u8> {
+    let geometry = &network.geometry;
+    let width = geometry.width as usize;
+    let half_box = (geometry.box_size / 2.0) as usize;
+    let lane_width = geometry.lane_width as usize;
+    let columns: Vec<usize> = network.nodes[..network.columns].iter().map(|node| node.x as usize).collect();
+    let rows: Vec<usize> = network.nodes.iter().step_by(network.columns).map(|node| node.y as usize).collect();
+
     for (index, pixel) in frame.chunks_exact_mut(4).enumerate() {
         let mut color = &[0x48, 0xb2, 0xe8, 0xff];
 
-        let i = index % WIDTH as usize;
-        let j = index / WIDTH as usize;
+        let i = index % width;
+        let j = index / width;
+
+        // Distance to the nearest north-south and east-west road centre
+        let dx = columns.iter().map(|&x| i.abs_diff(x)).min().unwrap_or(usize::MAX);
+        let dy = rows.iter().map(|&y| j.abs_diff(y)).min().unwrap_or(usize::MAX);
 
-        if i < WIDTH as usize / 2 + 50 && i > WIDTH as usize / 2 - 50 || j < HEIGHT as usize / 2 + 50 && j > HEIGHT as usize / 2 - 50 {
+        if dx < half_box || dy < half_box {
             color = &[0xa0, 0xa0, 0xa0, 0xff];
         }
 
-        if !(i < WIDTH as usize / 2 + 50 && i > WIDTH as usize / 2 - 50 && j < HEIGHT as usize / 2 + 50 && j > HEIGHT as usize / 2 - 50) {
-            if (i < WIDTH as usize / 2 + 5 && i > WIDTH as usize / 2 - 5) ^ (j < HEIGHT as usize / 2 + 5 && j > HEIGHT as usize / 2 - 5) {
-                color = &[0xff, 0xff, 0x00, 0xff];
-            } else if (i == WIDTH as usize / 2 - 25 || i == WIDTH as usize / 2 + 25) && (j / (DASH_LENGTH + GAP_LENGTH) % 2 == 0) {
-                color = &[0xff, 0xff, 0x00, 0xff];
-            } else if (j == HEIGHT as usize / 2 - 25 || j == HEIGHT as usize / 2 + 25) && (i / (DASH_LENGTH + GAP_LENGTH) % 2 == 0) {
+        if !(dx < half_box && dy < half_box) {
+            let center_line = (dx < 5) ^ (dy < 5);
+            let vertical_dash = dx == lane_width && (j / (DASH_LENGTH + GAP_LENGTH)).is_multiple_of(2);
+            let horizontal_dash = dy == lane_width && (i / (DASH_LENGTH + GAP_LENGTH)).is_multiple_of(2);
+            if center_line || vertical_dash || horizontal_dash {
                 color = &[0xff, 0xff, 0x00, 0xff];
             }
         }
@@ -226,6 +511,156 @@ fn load_background_frame(frame: &mut [u8]) -> Vec<u8> {
         pixel.copy_from_slice(color);
     }
 
-    return frame.to_vec();
+    frame.to_vec()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use rand::RngCore;
+    use crate::geometry::Geometry;
+    use crate::signal_controller::{Observation, SignalController, SignalRequest};
+    use crate::collision::create_vehicle_vision;
+    use crate::pedestrian::PedestrianState;
+    use crate::vehicle::TurnDirection;
+
+    // Keeps one phase of the plan green
+    struct Serve(usize);
+
+    impl SignalController for Serve {
+        fn decision_interval(&self) -> Duration {
+            Duration::ZERO
+        }
+
+        fn decide(&mut self, _observation: &Observation, _rng: &mut dyn RngCore) -> SignalRequest {
+            SignalRequest::Phase(self.0)
+        }
+    }
+
+    // One phase of the two-phase plan, 0 east-west or 1 north-south, green at every node and no
+    // spawning of its own
+    fn serving(phase: usize, network: Network) -> Simulation {
+        let mut scenario = Scenario { network, ..Scenario::default() };
+        scenario.demand.spawn_interval = Duration::MAX;
+        Simulation::new(None, &scenario, 0, &ControllerKind::Custom(Arc::new(move || Box::new(Serve(phase)))))
+    }
+
+    #[test]
+    fn intersection_volume_follows_the_vehicle_from_node_to_node() {
+        // Two nodes side by side
+        let mut simulation = serving(0, Network::grid(Geometry::default(), 2, 1, 200.0));
+        let geometry = simulation.network.node_geometry(0);
+        // Eastbound from the west edge, straight on at node 0
+        let vehicle = Vehicle::spawned(0, simulation.demand.initial_speed, &simulation.vehicle_types[0], &geometry, 1, TurnDirection::Straight, Duration::ZERO);
+        simulation.queue_for_release(vehicle.at_node(0));
+        let volumes = |simulation: &Simulation| simulation.intersections.iter().map(|intersection| intersection.manager.intersection_volume).collect::<Vec<_>>();
+        assert_eq!(volumes(&simulation), vec![[1, 0, 0, 0], [0, 0, 0, 0]]);
+
+        let dt = Duration::from_millis(50);
+        while simulation.vehicles.first().is_none_or(|vehicle| vehicle.node == 0) {
+            assert!(simulation.clock.now() < Duration::from_secs(60), "never reached node 1");
+            simulation.update(dt);
+        }
+        assert_eq!(volumes(&simulation), vec![[0, 0, 0, 0], [1, 0, 0, 0]]);
+
+        while !simulation.vehicles.is_empty() {
+            assert!(simulation.clock.now() < Duration::from_secs(120), "never left the map");
+            simulation.update(dt);
+        }
+        assert_eq!(volumes(&simulation), vec![[0, 0, 0, 0], [0, 0, 0, 0]]);
+        assert_eq!(simulation.completed_trips.len(), 1);
+    }
+
+    #[test]
+    fn turning_vehicles_give_way_to_pedestrians_on_their_exit_leg() {
+        let mut simulation = serving(0, Network::single(Geometry::default()));
+        let dt = Duration::from_millis(50);
+        simulation.update(dt);
+        simulation.update(dt);
+        assert!(simulation.intersections[0].manager.stop_lights[0].is_green());
+
+        // Eastbound turning right onto the south leg, 20 px short of its line
+        let geometry = simulation.network.node_geometry(0);
+        let mut vehicle = Vehicle::spawned(0, 0.0, &simulation.vehicle_types[0], &geometry, 1, TurnDirection::Right, Duration::ZERO);
+        let shift = vehicle.distance_to_box() - geometry.stop_line_setback - 20.0;
+        vehicle.bounds.x += shift;
+        vehicle.vision = create_vehicle_vision((vehicle.bounds.x, vehicle.bounds.y), vehicle.direction, VISION_LENGTH, vehicle.bounds.height);
+        simulation.vehicles.push(vehicle);
+        assert!(simulation.find_leaders()[0].is_none());
+
+        // On the north leg, away from its path
+        let mut pedestrian = Pedestrian::new(0, 0, 1, true, 40.0, Duration::ZERO);
+        pedestrian.state = PedestrianState::Crossing;
+        simulation.pedestrians.push(pedestrian);
+        assert!(simulation.find_leaders()[0].is_none());
+
+        simulation.pedestrians[0].leg = 3;
+        let leaders = simulation.find_leaders();
+        let leader = leaders[0].as_ref().expect("a pedestrian on the south leg");
+        assert!(leader.kind == LeaderKind::Yield);
+        assert!(leader.gap > 0.0 && leader.gap < 20.0);
+    }
+
+    #[test]
+    fn a_seed_fully_determines_a_run() {
+        let mut scenario = Scenario::default();
+        scenario.demand.flows = Some(vec![900.0; 8]);
+        let run = |seed| {
+            let mut simulation: Simulation = Simulation::new(None, &scenario, seed, &ControllerKind::QLearning);
+            let mut bounds = Vec::new();
+            for _ in 0..600 {
+                simulation.update(Duration::from_millis(10));
+                bounds.extend(simulation.vehicles.iter().map(|vehicle| (vehicle.id, vehicle.bounds.x, vehicle.bounds.y, vehicle.bounds.direction)));
+            }
+            (bounds, simulation.completed_trips)
+        };
+        let (bounds, trips) = run(7);
+        assert!(!trips.is_empty());
+        let (same_bounds, same_trips) = run(7);
+        assert!(bounds == same_bounds, "vehicles moved differently with the same seed");
+        assert_eq!(trips, same_trips);
+        assert!(bounds != run(8).0);
+    }
+
+    #[test]
+    fn spawning_follows_simulated_time() {
+        let mut scenario = Scenario::default();
+        scenario.demand.spawn_interval = Duration::from_secs(1);
+        let mut simulation: Simulation = Simulation::new(None, &scenario, 0, &ControllerKind::QLearning);
+        let dt = Duration::from_millis(250);
+        let mut spawned_at = Vec::new();
+        for step in 1..=10 {
+            simulation.update(dt);
+            assert_eq!(simulation.clock.now(), dt * step);
+            if simulation.id_counter > spawned_at.len() {
+                spawned_at.push(simulation.clock.now());
+            }
+        }
+        // One vehicle each time more than the interval has passed since the last
+        assert_eq!(spawned_at, vec![Duration::from_millis(1250), Duration::from_millis(2500)]);
+    }
+
+    #[test]
+    fn vehicles_brake_to_a_stop_at_a_red_line() {
+        let mut simulation = serving(1, Network::single(Geometry::default()));
+        let geometry = simulation.network.node_geometry(0);
+        let vehicle = Vehicle::spawned(0, simulation.demand.initial_speed, &simulation.vehicle_types[0], &geometry, 1, TurnDirection::Straight, Duration::ZERO);
+        simulation.queue_for_release(vehicle);
+
+        let dt = Duration::from_millis(10);
+        let mut slowest = f64::INFINITY;
+        for _ in 0..1000 {
+            simulation.update(dt);
+            let vehicle = &simulation.vehicles[0];
+            slowest = slowest.min(vehicle.acceleration);
+            // Its front never crosses the line
+            assert!(vehicle.distance_to_box() - vehicle.bounds.width as f64 / 2.0 > geometry.stop_line_setback);
+        }
+        let vehicle = &simulation.vehicles[0];
+        assert!(vehicle.is_stopped() && vehicle.stopped_at_line);
+        assert!(vehicle.distance_to_box() - geometry.stop_line_setback < 20.0, "stopped {} short of the line", vehicle.distance_to_box());
+        // Gradually, not with a hard stop
+        assert!(slowest > -vehicle.model.comfortable_deceleration() * 2.0, "braked at {}", slowest);
+    }
+}
@@ -1,54 +1,236 @@
 use crate::collision::Rectangle;
-use crate::config::{WIDTH, HEIGHT};
 use crate::drawing_util::draw_rectangle;
-use std::time::{Duration, Instant};
+use crate::geometry::Geometry;
+use crate::vehicle::Lane;
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SignalState {
+    Green,
+    Yellow,
+    AllRed, // Red, but still clearing the box before any conflicting head may turn green
+    Red,
+}
+
+#[derive(Clone, Copy)]
+pub struct SignalTimings {
+    pub min_green: Duration,
+    pub yellow: Duration,
+    pub all_red: Duration,
+}
+
+impl Default for SignalTimings {
+    fn default() -> Self {
+        Self {
+            min_green: Duration::from_millis(500),
+            yellow: Duration::from_millis(300),
+            all_red: Duration::from_millis(200),
+        }
+    }
+}
 
 pub struct StopLight {
     pub line: Rectangle,
-    pub time_since_flip: Instant,
-    pub active: bool,
+    pub heading: f64, // Direction of travel of the approach this line controls
+    pub state: SignalState,
+    pub entered_at: Duration,
+    pub green_requested: bool,
+    pub timings: SignalTimings,
+    lane_width: f64,
 }
 
 impl StopLight {
 
-    pub fn new(lane: u32) -> Self {
-
-        let (line, timer_offset) = match lane {
-            0 => (Rectangle::new(WIDTH as f64 / 2.0 - 60.0, HEIGHT as f64 / 2.0 + 25.0, 1, 50, 0.0), 0.0),
-            1 => (Rectangle::new(WIDTH as f64 / 2.0 - 25.0, HEIGHT as f64 / 2.0 - 60.0, 50, 1, 0.0), 4.0),
-            2 => (Rectangle::new(WIDTH as f64 / 2.0 + 60.0, HEIGHT as f64 / 2.0 - 25.0, 1, 50, 0.0), 8.0),
-            3 => (Rectangle::new(WIDTH as f64 / 2.0 + 25.0, HEIGHT as f64 / 2.0 + 60.0, 50, 1, 0.0), 12.0),
+    pub fn new(lane: u32, geometry: &Geometry) -> Self {
+        let (center_x, center_y) = geometry.center();
+        // Lines sit across both entrance lanes, setback before the box
+        let distance = geometry.box_size / 2.0 + geometry.stop_line_setback;
+        let across = (geometry.lane_width * 2.0).round() as u32;
 
+        let line = match lane {
+            0 => Rectangle::new(center_x - distance, center_y + geometry.lane_width, 1, across, 0.0),
+            1 => Rectangle::new(center_x - geometry.lane_width, center_y - distance, across, 1, 0.0),
+            2 => Rectangle::new(center_x + distance, center_y - geometry.lane_width, 1, across, 0.0),
+            3 => Rectangle::new(center_x + geometry.lane_width, center_y + distance, across, 1, 0.0),
           _ => unreachable!(),
         };
 
 
         Self {
             line,
-            time_since_flip: Instant::now(),
-            active: false,
+            heading: lane as f64 * std::f64::consts::PI / 2.0,
+            state: SignalState::Red,
+            entered_at: Duration::ZERO,
+            green_requested: false,
+            timings: SignalTimings::default(),
+            lane_width: geometry.lane_width,
         }
     }
 
-    pub fn update(&mut self) {
+    // Head for the left turns and U-turns of an approach, across its inside lane only
+    pub fn left_turn(lane: u32, geometry: &Geometry) -> Self {
+        let mut light = Self::new(lane, geometry);
+        let shift = geometry.lane_width / 2.0;
+        light.line.x += shift * light.heading.sin();
+        light.line.y -= shift * light.heading.cos();
+        let width = geometry.lane_width.round() as u32;
+        if light.line.width == 1 {
+            light.line.height = width;
+        } else {
+            light.line.width = width;
+        }
+        light
+    }
 
+    // Controllers only ever ask; the state machine decides when the change is safe to show
+    pub fn request(&mut self, green: bool) {
+        self.green_requested = green;
     }
 
-    pub fn flip(&mut self) {
-        self.time_since_flip = Instant::now();
-        self.active = !self.active;
+    // blocked is true while a conflicting head at the intersection is still green, yellow or all-red;
+    // held is true while pedestrians crossing with this head still have WALK or flashing DON'T WALK,
+    // which extends green past min_green
+    pub fn update(&mut self, now: Duration, blocked: bool, held: bool) {
+        let in_state = now.saturating_sub(self.entered_at);
+
+        match self.state {
+            SignalState::Green => {
+                if !self.green_requested && in_state >= self.timings.min_green && !held {
+                    self.enter(SignalState::Yellow, now);
+                }
+            },
+            SignalState::Yellow => {
+                if in_state >= self.timings.yellow {
+                    self.enter(SignalState::AllRed, now);
+                }
+            },
+            SignalState::AllRed => {
+                if in_state >= self.timings.all_red {
+                    self.enter(SignalState::Red, now);
+                }
+            },
+            SignalState::Red => {
+                if self.green_requested && !blocked {
+                    self.enter(SignalState::Green, now);
+                }
+            },
+        }
     }
 
-    pub fn draw(&self, frame: &mut [u8], frame_width: u32, frame_height: u32) {
+    fn enter(&mut self, state: SignalState, now: Duration) {
+        self.state = state;
+        self.entered_at = now;
+    }
 
-        let mut color = [255, 0, 0, 255];
+    // Stretch of one entrance lane, ending setback pixels before the line
+    pub fn lane_area(&self, lane: &Lane, setback: f64, length: u32) -> Rectangle {
+        let distance = setback + length as f64 / 2.0;
+        // The line spans both lanes; the right lane lies on the driver's right of its centre
+        let offset = match lane {
+            Lane::Left => -self.lane_width / 2.0,
+            Lane::Right => self.lane_width / 2.0,
+        };
+        Rectangle::new(
+            self.line.x - distance * self.heading.cos() - offset * self.heading.sin(),
+            self.line.y - distance * self.heading.sin() + offset * self.heading.cos(),
+            length,
+            self.lane_width.round() as u32,
+            self.heading,
+        )
+    }
 
-        if self.active {
-            color = [0, 255, 0, 255];
-        }
+    pub fn is_green(&self) -> bool {
+        self.state == SignalState::Green
+    }
+
+    // Whether vehicles must stop at the line; yellow is left to the driver's stopping distance
+    pub fn requires_stop(&self) -> bool {
+        matches!(self.state, SignalState::AllRed | SignalState::Red)
+    }
+
+    pub fn draw(&self, frame: &mut [u8], frame_width: u32, frame_height: u32) {
+
+        let color = match self.state {
+            SignalState::Green => [0, 255, 0, 255],
+            SignalState::Yellow => [255, 160, 0, 255],
+            SignalState::AllRed | SignalState::Red => [255, 0, 0, 255],
+        };
 
         draw_rectangle(frame, frame_width, frame_height, &self.line, color, true);
         //self.draw_rectangle(frame, frame_width, frame_height, &self.vision, [0,255,0,255], false);
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn green_clears_through_yellow_and_all_red() {
+        // 500 ms minimum green, 300 ms yellow, 200 ms all-red
+        let mut light = StopLight::new(0, &Geometry::default());
+        light.request(true);
+        light.update(Duration::ZERO, false, false);
+        assert!(light.is_green());
+
+        light.request(false);
+        light.update(Duration::from_millis(400), false, false);
+        assert!(light.is_green());
+        light.update(Duration::from_millis(500), false, false);
+        assert_eq!(light.state, SignalState::Yellow);
+        assert!(!light.requires_stop());
+        light.update(Duration::from_millis(700), false, false);
+        assert_eq!(light.state, SignalState::Yellow);
+        light.update(Duration::from_millis(800), false, false);
+        assert_eq!(light.state, SignalState::AllRed);
+        assert!(light.requires_stop());
+        light.update(Duration::from_millis(900), false, false);
+        assert_eq!(light.state, SignalState::AllRed);
+        light.update(Duration::from_millis(1000), false, false);
+        assert_eq!(light.state, SignalState::Red);
+        assert_eq!(light.entered_at, Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn a_request_back_to_green_does_not_cut_clearance_short() {
+        let mut light = StopLight::new(0, &Geometry::default());
+        light.request(true);
+        light.update(Duration::ZERO, false, false);
+        light.request(false);
+        light.update(Duration::from_millis(500), false, false);
+        light.request(true);
+        light.update(Duration::from_millis(600), false, false);
+        assert_eq!(light.state, SignalState::Yellow);
+        light.update(Duration::from_millis(800), false, false);
+        assert_eq!(light.state, SignalState::AllRed);
+        light.update(Duration::from_millis(1000), false, false);
+        assert_eq!(light.state, SignalState::Red);
+        light.update(Duration::from_millis(1100), false, false);
+        assert!(light.is_green());
+    }
+
+    #[test]
+    fn blocked_heads_wait_at_red() {
+        let mut light = StopLight::new(1, &Geometry::default());
+        light.request(true);
+        light.update(Duration::ZERO, true, false);
+        light.update(Duration::from_millis(1000), true, false);
+        assert_eq!(light.state, SignalState::Red);
+        light.update(Duration::from_millis(1100), false, false);
+        assert!(light.is_green());
+        assert_eq!(light.entered_at, Duration::from_millis(1100));
+    }
+
+    #[test]
+    fn held_heads_stay_green() {
+        let mut light = StopLight::new(2, &Geometry::default());
+        light.request(true);
+        light.update(Duration::ZERO, false, false);
+        light.request(false);
+        light.update(Duration::from_millis(2000), false, true);
+        assert!(light.is_green());
+        light.update(Duration::from_millis(2100), false, false);
+        assert_eq!(light.state, SignalState::Yellow);
+    }
+}
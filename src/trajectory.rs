@@ -0,0 +1,226 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use rand::{Rng, SeedableRng};
+use crate::recorder::seeded_path;
+use crate::simulation::Simulation;
+use crate::vehicle::{Lane, State, TurnDirection, Vehicle};
+
+// Columns follow the NGSIM trajectory files where there is an equivalent; positions are the vehicle
+// centre in pixels, speeds in pixels per simulated second
+const CSV_HEADER: &str = "Vehicle_ID,Frame_ID,Global_Time,Local_X,Local_Y,Heading,v_Length,v_Width,v_Vel,v_Acc,Lane_ID,Node_ID,Entrance,Movement,State";
+
+// Binary files start with this magic, then one fixed-size little-endian record per vehicle per step:
+// u64 vehicle id, u64 frame, u64 time in microseconds, f32 x, y, heading, length, width, speed,
+// acceleration, u32 node, then u8 lane id, entrance, movement (0 left, 1 straight, 2 right, 3 U-turn)
+// and state (0 driving, 1 turning, 2 stop). 60 bytes per record. Entrance and movement are at the node.
+const BINARY_MAGIC: &[u8; 8] = b"TRAJ\x00\x00\x00\x02";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TrajectoryFormat {
+    Csv,
+    Binary,
+}
+
+impl TrajectoryFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(TrajectoryFormat::Csv),
+            "binary" | "bin" => Some(TrajectoryFormat::Binary),
+            _ => None,
+        }
+    }
+
+    // .bin files get the binary format, anything else CSV
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("bin") => TrajectoryFormat::Binary,
+            _ => TrajectoryFormat::Csv,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TrajectoryConfig {
+    pub path: PathBuf,
+    pub format: TrajectoryFormat,
+}
+
+impl TrajectoryConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self {
+            format: TrajectoryFormat::from_path(&path),
+            path,
+        }
+    }
+
+    pub fn for_seed(&self, seed: u64) -> Self {
+        Self {
+            path: seeded_path(&self.path, seed),
+            ..self.clone()
+        }
+    }
+}
+
+// Writes every vehicle on the map after every simulation step
+pub struct TrajectoryLogger {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    format: TrajectoryFormat,
+    frame: u64,
+    wrote_header: bool,
+}
+
+impl TrajectoryLogger {
+    pub fn new(writer: Box<dyn Write + Send>, format: TrajectoryFormat) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            format,
+            frame: 0,
+            wrote_header: false,
+        }
+    }
+
+    pub fn create(config: &TrajectoryConfig) -> io::Result<Self> {
+        let file = File::create(&config.path)?;
+        Ok(Self::new(Box::new(file), config.format))
+    }
+
+    pub fn log<R: Rng + SeedableRng>(&mut self, simulation: &Simulation<R>) -> io::Result<()> {
+        if !self.wrote_header {
+            match self.format {
+                TrajectoryFormat::Csv => writeln!(self.writer, "{}", CSV_HEADER)?,
+                TrajectoryFormat::Binary => self.writer.write_all(BINARY_MAGIC)?,
+            }
+            self.wrote_header = true;
+        }
+
+        self.frame += 1;
+        let time = simulation.clock.now();
+        for vehicle in simulation.vehicles() {
+            match self.format {
+                TrajectoryFormat::Csv => writeln!(self.writer, "{},{},{},{:.3},{:.3},{:.5},{},{},{:.3},{:.3},{},{},{},{:?},{:?}",
+                    vehicle.id, self.frame, time.as_millis(), vehicle.bounds.x, vehicle.bounds.y, vehicle.direction,
+                    vehicle.bounds.width, vehicle.bounds.height, vehicle.speed, vehicle.acceleration,
+                    lane_id(vehicle), vehicle.node, vehicle.entrance, vehicle.turn, vehicle.state())?,
+                TrajectoryFormat::Binary => self.write_record(vehicle, time.as_micros() as u64)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn write_record(&mut self, vehicle: &Vehicle, time: u64) -> io::Result<()> {
+        let mut record = Vec::with_capacity(60);
+        record.extend_from_slice(&(vehicle.id as u64).to_le_bytes());
+        record.extend_from_slice(&self.frame.to_le_bytes());
+        record.extend_from_slice(&time.to_le_bytes());
+        for value in [vehicle.bounds.x, vehicle.bounds.y, vehicle.direction, vehicle.bounds.width as f64,
+                      vehicle.bounds.height as f64, vehicle.speed, vehicle.acceleration] {
+            record.extend_from_slice(&(value as f32).to_le_bytes());
+        }
+        record.extend_from_slice(&(vehicle.node as u32).to_le_bytes());
+        let movement = match vehicle.turn {
+            TurnDirection::Left => 0,
+            TurnDirection::Straight => 1,
+            TurnDirection::Right => 2,
+            TurnDirection::UTurn => 3,
+        };
+        let state = match vehicle.state() {
+            State::Driving => 0,
+            State::Turning => 1,
+            State::Stop => 2,
+        };
+        record.extend_from_slice(&[lane_id(vehicle), vehicle.entrance as u8, movement, state]);
+        self.writer.write_all(&record)
+    }
+}
+
+// NGSIM numbers lanes from the inside out
+fn lane_id(vehicle: &Vehicle) -> u8 {
+    match vehicle.lane {
+        Lane::Left => 1,
+        Lane::Right => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::scenario::Scenario;
+    use crate::signal_controller::ControllerKind;
+    use super::*;
+
+    // Lets a test read back what the logger wrote through its boxed writer
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Logs 10 s of traffic at 100 ms steps; also returns how many vehicles were on the map each step
+    fn log(format: TrajectoryFormat) -> (Vec<u8>, Vec<usize>) {
+        let mut scenario = Scenario::default();
+        scenario.demand.flows = Some(vec![900.0; 8]);
+        let mut simulation: Simulation = Simulation::new(None, &scenario, 0, &ControllerKind::QLearning);
+        let output = Shared::default();
+        let mut logger = TrajectoryLogger::new(Box::new(output.clone()), format);
+        let mut on_map = Vec::new();
+        for _ in 0..100 {
+            simulation.update(Duration::from_millis(100));
+            logger.log(&simulation).unwrap();
+            on_map.push(simulation.vehicles().len());
+        }
+        logger.flush().unwrap();
+        let bytes = output.0.lock().unwrap().clone();
+        (bytes, on_map)
+    }
+
+    #[test]
+    fn csv_rows_have_every_column() {
+        let (bytes, on_map) = log(TrajectoryFormat::Csv);
+        let text = String::from_utf8(bytes).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines.len(), 1 + on_map.iter().sum::<usize>());
+        assert!(lines.len() > 1, "no vehicles were logged");
+
+        let columns = CSV_HEADER.split(',').count();
+        for line in &lines[1..] {
+            assert_eq!(line.split(',').count(), columns, "{}", line);
+        }
+    }
+
+    #[test]
+    fn binary_records_are_60_bytes_after_the_magic() {
+        let (bytes, on_map) = log(TrajectoryFormat::Binary);
+        assert_eq!(&bytes[..8], BINARY_MAGIC);
+        let records = on_map.iter().sum::<usize>();
+        assert!(records > 0, "no vehicles were logged");
+        assert_eq!(bytes.len(), 8 + 60 * records);
+
+        // Frames count up from 1 and time is in microseconds
+        let u64_at = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let first_frame = on_map.iter().position(|&count| count > 0).unwrap() as u64 + 1;
+        assert_eq!(u64_at(8 + 8), first_frame);
+        assert_eq!(u64_at(8 + 16), first_frame * 100_000);
+        for record in bytes[8..].chunks(60) {
+            assert!(record[56] == 1 || record[56] == 2); // Lane id
+            assert!(record[57] < 8); // Entrance
+            assert!(record[58] < 4); // Movement
+            assert!(record[59] < 3); // State
+        }
+    }
+}
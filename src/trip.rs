@@ -0,0 +1,137 @@
+use std::time::Duration;
+use crate::vehicle::TurnDirection;
+
+// Running totals kept on every vehicle while it is in the simulation
+#[derive(Default)]
+pub struct TripLog {
+    pub spawned_at: Duration,
+    pub origin_node: usize,
+    pub origin_entrance: u32, // Where the vehicle entered the network
+    pub origin_turn: Option<TurnDirection>, // Set once the vehicle moves on from its first node
+    pub entered_at: Option<Duration>, // Left the release queue and started driving
+    pub distance: f64,
+    pub stops: u32,
+    pub stopped_time: Duration,
+    pub segment: SegmentStart, // Totals when the vehicle was handed over to the node it is at now
+    stopped: bool,
+}
+
+// Where the part of a trip at one node starts; at is None at the first node, which the vehicle
+// has been at since it spawned
+#[derive(Clone, Copy, Default)]
+pub struct SegmentStart {
+    pub at: Option<Duration>,
+    pub stops: u32,
+    pub stopped_time: Duration,
+    pub distance: f64,
+}
+
+impl TripLog {
+    pub fn new(spawned_at: Duration, origin_entrance: u32) -> Self {
+        Self {
+            spawned_at,
+            origin_entrance,
+            ..Self::default()
+        }
+    }
+
+    pub fn record_step(&mut self, dt: Duration, speed: f64, stopped: bool) {
+        self.distance += speed * dt.as_secs_f64();
+        if stopped {
+            self.stopped_time += dt;
+            if !self.stopped {
+                self.stops += 1;
+            }
+        }
+        self.stopped = stopped;
+    }
+
+    // Starts the part of the trip at the next node, the vehicle having cleared a box at now
+    pub fn start_segment(&mut self, now: Duration) {
+        self.segment = SegmentStart {
+            at: Some(now),
+            stops: self.stops,
+            stopped_time: self.stopped_time,
+            distance: self.distance,
+        };
+    }
+}
+
+// A whole trip, emitted once per vehicle when it leaves the map, or the part of it at one node,
+// emitted as the vehicle moves on from that node
+#[derive(Clone, PartialEq, Debug)]
+pub struct TripRecord {
+    pub vehicle_id: usize,
+    pub node: usize, // Node and entrance the trip, or its part, started at
+    pub entrance: u32,
+    pub turn: TurnDirection, // Made at that node
+    pub spawned_at: Duration,
+    pub entered_at: Duration,
+    pub exited_at: Duration,
+    pub stops: u32,
+    pub stopped_time: Duration,
+    pub distance: f64,
+    pub free_flow_time: Duration, // Time the same distance takes at the driver's desired speed
+}
+
+impl TripRecord {
+    pub fn queue_time(&self) -> Duration {
+        self.entered_at.saturating_sub(self.spawned_at)
+    }
+
+    pub fn travel_time(&self) -> Duration {
+        self.exited_at.saturating_sub(self.entered_at)
+    }
+
+    // Everything beyond free-flow travel, including the wait to enter the map
+    pub fn control_delay(&self) -> Duration {
+        (self.queue_time() + self.travel_time()).saturating_sub(self.free_flow_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_stop_counts_once_however_long_it_lasts() {
+        let step = Duration::from_millis(100);
+        let mut log = TripLog::new(Duration::ZERO, 0);
+        for stopped in [false, true, true, true, false, true, false] {
+            log.record_step(step, if stopped { 0.0 } else { 10.0 }, stopped);
+        }
+        assert_eq!(log.stops, 2);
+        assert_eq!(log.stopped_time, Duration::from_millis(400));
+        assert!((log.distance - 3.0).abs() < 1e-9);
+
+        log.start_segment(Duration::from_millis(700));
+        assert_eq!(log.segment.at, Some(Duration::from_millis(700)));
+        assert_eq!(log.segment.stops, 2);
+        assert_eq!(log.segment.stopped_time, Duration::from_millis(400));
+    }
+
+    #[test]
+    fn control_delay_includes_the_wait_to_enter() {
+        let mut record = TripRecord {
+            vehicle_id: 0,
+            node: 0,
+            entrance: 0,
+            turn: TurnDirection::Straight,
+            spawned_at: Duration::from_secs(10),
+            entered_at: Duration::from_secs(14),
+            exited_at: Duration::from_secs(30),
+            stops: 1,
+            stopped_time: Duration::from_secs(5),
+            distance: 100.0,
+            free_flow_time: Duration::from_secs(8),
+        };
+        assert_eq!(record.queue_time(), Duration::from_secs(4));
+        assert_eq!(record.travel_time(), Duration::from_secs(16));
+        assert_eq!(record.control_delay(), Duration::from_secs(12));
+
+        // A trip faster than free flow has no delay rather than a negative one
+        record.entered_at = record.spawned_at;
+        record.exited_at = Duration::from_secs(15);
+        assert_eq!(record.control_delay(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,128 @@
+use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use crate::network::{entrance_for, Network};
+use crate::signal_plan::APPROACHES;
+use crate::vehicle::{Lane, TurnDirection, TURN_DIRECTIONS};
+
+// Relative numbers of an approach's vehicles making each movement, e.g. the counts from a
+// turning-movement survey; they need not add up to one
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TurnShares {
+    pub left: f64,
+    pub through: f64,
+    pub right: f64,
+    pub u_turn: f64,
+}
+
+impl Default for TurnShares {
+    // What random turns come to when both lanes are equally busy
+    fn default() -> Self {
+        Self {
+            left: 1.0,
+            through: 1.0,
+            right: 1.0,
+            u_turn: 0.0,
+        }
+    }
+}
+
+impl TurnShares {
+    pub fn share(&self, turn: TurnDirection) -> f64 {
+        match turn {
+            TurnDirection::Left => self.left,
+            TurnDirection::Straight => self.through,
+            TurnDirection::Right => self.right,
+            TurnDirection::UTurn => self.u_turn,
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        TURN_DIRECTIONS.iter().map(|&turn| self.share(turn)).sum()
+    }
+}
+
+// Turn shares for every approach of every node. A vehicle first draws its movement from its
+// approach's shares, then takes a lane the network allows that movement from.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TurningRatios {
+    shares: Vec<[TurnShares; APPROACHES]>, // Per node
+}
+
+impl TurningRatios {
+    pub fn new(nodes: usize) -> Self {
+        Self {
+            shares: vec![[TurnShares::default(); APPROACHES]; nodes],
+        }
+    }
+
+    pub fn set(&mut self, node: usize, approach: usize, shares: TurnShares) {
+        self.shares[node][approach] = shares;
+    }
+
+    pub fn shares(&self, node: usize, approach: usize) -> &TurnShares {
+        &self.shares[node][approach]
+    }
+
+    // Movement for a vehicle arriving on approach of node in lane, and the lane it has to make it
+    // from. Through traffic stays in its lane when that lane carries it.
+    pub fn choose<R: Rng + ?Sized>(&self, network: &Network, node: usize, approach: usize, lane: Lane, rng: &mut R) -> (Lane, TurnDirection) {
+        let shares = self.shares(node, approach);
+        let weights = TURN_DIRECTIONS.map(|turn| shares.share(turn));
+        let turn = TURN_DIRECTIONS[WeightedIndex::new(weights).expect("Turn shares are validated when loaded").sample(rng)];
+
+        let other = if lane == Lane::Left { Lane::Right } else { Lane::Left };
+        let lane = [lane, other].into_iter()
+            .find(|&lane| network.turns_from(node, entrance_for(approach, lane)).contains(&turn))
+            .expect("Every movement is allowed from one of the lanes");
+        (lane, turn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::geometry::Geometry;
+
+    #[test]
+    fn movements_take_the_lane_that_carries_them() {
+        let network = Network::single(Geometry::default());
+        let mut ratios = TurningRatios::new(1);
+        let mut rng = StdRng::seed_from_u64(0);
+        let none = TurnShares { left: 0.0, through: 0.0, right: 0.0, u_turn: 0.0 };
+        let cases = [
+            (TurnShares { left: 1.0, ..none }, TurnDirection::Left, Lane::Right, Lane::Left),
+            (TurnShares { u_turn: 1.0, ..none }, TurnDirection::UTurn, Lane::Right, Lane::Left),
+            (TurnShares { right: 1.0, ..none }, TurnDirection::Right, Lane::Left, Lane::Right),
+            (TurnShares { through: 1.0, ..none }, TurnDirection::Straight, Lane::Left, Lane::Left),
+            (TurnShares { through: 1.0, ..none }, TurnDirection::Straight, Lane::Right, Lane::Right),
+        ];
+        for (shares, turn, arriving, expected) in cases {
+            ratios.set(0, 2, shares);
+            assert_eq!(ratios.choose(&network, 0, 2, arriving, &mut rng), (expected, turn));
+        }
+    }
+
+    #[test]
+    fn movements_follow_the_shares() {
+        let network = Network::single(Geometry::default());
+        let mut ratios = TurningRatios::new(1);
+        ratios.set(0, 0, TurnShares { left: 1.0, through: 3.0, right: 0.0, u_turn: 0.0 });
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let draws = 4000;
+        let through = (0..draws)
+            .filter(|_| ratios.choose(&network, 0, 0, Lane::Left, &mut rng).1 == TurnDirection::Straight)
+            .count();
+        let share = through as f64 / draws as f64;
+        assert!((share - 0.75).abs() < 0.03, "through share {}", share);
+    }
+
+    #[test]
+    fn default_shares_leave_out_u_turns() {
+        let shares = TurnShares::default();
+        assert_eq!(shares.total(), 3.0);
+        assert_eq!(shares.share(TurnDirection::UTurn), 0.0);
+    }
+}
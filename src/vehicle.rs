@@ -1,110 +1,238 @@
 use std::time::Duration;
-use crate::config::{WIDTH, HEIGHT};
+use crate::config::{VISION_LENGTH, STOPPED_SPEED};
+use crate::geometry::Geometry;
+use crate::network::entrance_for;
+use crate::routing::Route;
 use crate::collision::{Rectangle, create_vehicle_vision};
 use crate::drawing_util::draw_rectangle;
+use crate::trip::{TripLog, TripRecord};
+use crate::car_following::{CarFollowingModel, IdmParams, Leader, ModelKind};
+use rand::RngCore;
 use rand::Rng;
 
 pub struct Vehicle {
     pub id: usize,
-    pub speed: u32,
+    pub speed: f64,
+    pub acceleration: f64,
+    pub model: Box<dyn CarFollowingModel>,
     pub bounds: Rectangle,
+    pub previous_bounds: Rectangle,
     pub vision: Rectangle,
     pub direction: f64,
     state: State,
     pub lane: Lane,
-    turn: TurnDirection,
-    pub entrance: u32,
+    pub turn: TurnDirection,
+    pub entrance: u32, // Entrance lane at the node it is approaching or crossing
+    pub node: usize,
+    entered_box: bool,
+    pub stopped_at_line: bool, // Has come to a full stop at a red line of the node it is approaching
+    pub trip: TripLog,
+    geometry: Geometry,
+    pub route: Option<Route>, // Vehicles without one pick a random turn at every node
+    route_leg: usize, // Nodes of the route already passed
+    pub next_step: Option<(Lane, TurnDirection)>, // Chosen for the next node but held back by a lane change
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy)]
+pub struct VehicleType {
+    pub width: u32,
+    pub height: u32,
+    pub share: f64, // Relative weight when picking a type for a new vehicle
+    pub model: ModelKind,
+}
+
+impl Default for VehicleType {
+    fn default() -> Self {
+        Self {
+            width: 10,
+            height: 10,
+            share: 1.0,
+            model: ModelKind::Idm(IdmParams::default()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum State {
     Driving,
     Turning,
     Stop,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum TurnDirection {
     Left,
     Straight,
     Right,
+    UTurn, // From the inside lane, back into the inside lane of the other direction
 }
 
-#[derive(PartialEq)]
+pub const TURN_DIRECTIONS: [TurnDirection; 4] = [TurnDirection::Left, TurnDirection::Straight, TurnDirection::Right, TurnDirection::UTurn];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum Lane {
     Left,
     Right,
 }
 
+// Inside lanes mostly turn left and outside lanes mostly turn right; a third go straight
+pub fn random_turn<R: Rng + ?Sized>(lane: Lane, rng: &mut R) -> TurnDirection {
+    match rng.gen_range(0..3) {
+        0 | 1 => if lane == Lane::Left {
+            TurnDirection::Left
+        } else {
+            TurnDirection::Right
+        },
+        2 => TurnDirection::Straight,
+        _ => unreachable!(),
+    }
+}
+
 impl Vehicle {
 
-    pub fn new(id: usize, speed: u32, width: u32, height: u32, entrance: u32) -> Self {
+    pub fn new<R: Rng + ?Sized>(id: usize, speed: f64, vehicle_type: &VehicleType, geometry: &Geometry, entrance: u32, spawned_at: Duration, rng: &mut R) -> Self {
+        let lane = if entrance.is_multiple_of(2) { Lane::Left } else { Lane::Right };
+        let turn = random_turn(lane, rng);
+        Self::spawned(id, speed, vehicle_type, geometry, entrance, turn, spawned_at)
+    }
 
-        let (x,y,direction,lane) = match entrance {
-            0 => (0.0, HEIGHT as f64 /2.0 + 12.5, 0.0, Lane::Left),
-            1 => (0.0, HEIGHT as f64 /2.0 + 37.5, 0.0, Lane::Right),
-            2 => (WIDTH as f64 /2.0 - 12.5, 0.0, std::f64::consts::PI/2.0, Lane::Left),
-            3 => (WIDTH as f64 /2.0 - 37.5, 0.0, std::f64::consts::PI/2.0, Lane::Right),
-            4 => (WIDTH as f64, HEIGHT as f64 /2.0 - 12.5, std::f64::consts::PI, Lane::Left),
-            5 => (WIDTH as f64, HEIGHT as f64 /2.0 - 37.5, std::f64::consts::PI, Lane::Right),
-            6 => (WIDTH as f64 /2.0 + 12.5, HEIGHT as f64, std::f64::consts::PI*1.5, Lane::Left),
-            7 => (WIDTH as f64 /2.0 + 37.5, HEIGHT as f64, std::f64::consts::PI*1.5, Lane::Right),
-            _ => unreachable!(),
-        };
+    // A new vehicle that makes turn at its first node
+    pub fn spawned(id: usize, speed: f64, vehicle_type: &VehicleType, geometry: &Geometry, entrance: u32, turn: TurnDirection, spawned_at: Duration) -> Self {
+        let mut vehicle = Self::with_turn(id, speed, vehicle_type, geometry, entrance, turn);
+        vehicle.trip = TripLog::new(spawned_at, entrance);
+        vehicle
+    }
+
+    // A new vehicle at its route's origin, in the route's lane
+    pub fn on_route(id: usize, speed: f64, vehicle_type: &VehicleType, geometry: &Geometry, route: Route, spawned_at: Duration) -> Self {
+        let (entrance, node) = (route.entrance(), route.origin.node);
+        let mut vehicle = Self::spawned(id, speed, vehicle_type, geometry, entrance, route.turns[0], spawned_at);
+        vehicle.route = Some(route);
+        vehicle.at_node(node)
+    }
 
+    // Places a new vehicle on the approach of a node other than the first
+    pub fn at_node(mut self, node: usize) -> Self {
+        self.node = node;
+        self.trip.origin_node = node;
+        self
+    }
 
-        let mut rng = rand::thread_rng();
+    // Hands a vehicle that has left one intersection at now over to the next one on its heading,
+    // approaching it in lane
+    pub fn enter_node(&mut self, node: usize, geometry: &Geometry, lane: Lane, turn: TurnDirection, now: Duration) {
+        let heading = self.exit_heading().expect("Only vehicles that have cleared a box move on to the next node");
+        self.trip.origin_turn.get_or_insert(self.turn);
+        self.trip.start_segment(now);
+        if lane != self.lane {
+            // The lane change happens on the link, drawn as one sideways step as the vehicle leaves the
+            // box. The simulation waits for the other lane to be clear alongside, see lane_change_bounds.
+            let shift = geometry.lane_offset(&lane) - geometry.lane_offset(&self.lane);
+            for bounds in [&mut self.bounds, &mut self.previous_bounds] {
+                bounds.x -= shift * self.direction.sin();
+                bounds.y += shift * self.direction.cos();
+            }
+            self.lane = lane;
+        }
+        self.node = node;
+        self.geometry = *geometry;
+        self.entrance = entrance_for(heading, self.lane);
+        self.turn = turn;
+        self.entered_box = false;
+        self.stopped_at_line = false;
+        self.route_leg += 1;
+        self.next_step = None;
+    }
 
-        let turn = match rng.gen_range(0..3) {
-            0 => if lane == Lane::Left {
-                TurnDirection::Left
-            } else {
-                TurnDirection:: Right
-            },
-            1 => if lane == Lane::Left {
-                TurnDirection::Left
-            } else {
-                TurnDirection:: Right
-            },
-            2 => TurnDirection::Straight,
+    // Where the vehicle would be after moving sideways into lane
+    pub fn lane_change_bounds(&self, geometry: &Geometry, lane: Lane) -> Rectangle {
+        let shift = geometry.lane_offset(&lane) - geometry.lane_offset(&self.lane);
+        let mut bounds = self.bounds.clone();
+        bounds.x -= shift * self.direction.sin();
+        bounds.y += shift * self.direction.cos();
+        bounds
+    }
+
+    // Lane the vehicle's route approaches the node after the one it is at in, and the turn it makes there
+    pub fn next_route_step(&self) -> Option<(Lane, TurnDirection)> {
+        let route = self.route.as_ref()?;
+        let leg = self.route_leg + 1;
+        Some((*route.lanes.get(leg)?, *route.turns.get(leg)?))
+    }
+
+    pub fn with_turn(id: usize, speed: f64, vehicle_type: &VehicleType, geometry: &Geometry, entrance: u32, turn: TurnDirection) -> Self {
+        let (center_x, center_y) = geometry.center();
+        let (width, height) = (geometry.width as f64, geometry.height as f64);
+        let inner = geometry.lane_offset(&Lane::Left);
+        let outer = geometry.lane_offset(&Lane::Right);
+
+        let (x,y,direction,lane) = match entrance {
+            0 => (0.0, center_y + inner, 0.0, Lane::Left),
+            1 => (0.0, center_y + outer, 0.0, Lane::Right),
+            2 => (center_x - inner, 0.0, std::f64::consts::PI/2.0, Lane::Left),
+            3 => (center_x - outer, 0.0, std::f64::consts::PI/2.0, Lane::Right),
+            4 => (width, center_y - inner, std::f64::consts::PI, Lane::Left),
+            5 => (width, center_y - outer, std::f64::consts::PI, Lane::Right),
+            6 => (center_x + inner, height, std::f64::consts::PI*1.5, Lane::Left),
+            7 => (center_x + outer, height, std::f64::consts::PI*1.5, Lane::Right),
             _ => unreachable!(),
         };
 
-
-        let bounds = Rectangle::new(x,y,width,height,direction);
-        let vision = create_vehicle_vision((x,y), direction, 20, 10);
+        let bounds = Rectangle::new(x,y,vehicle_type.width,vehicle_type.height,direction);
+        let previous_bounds = bounds.clone();
+        let vision = create_vehicle_vision((x,y), direction, VISION_LENGTH, vehicle_type.height);
 
         Self {
             id,
             speed,
+            acceleration: 0.0,
+            model: vehicle_type.model.build(),
             bounds,
+            previous_bounds,
             vision,
             direction,
             state: State::Driving,
+            entered_box: false,
+            stopped_at_line: false,
+            trip: TripLog::default(),
             lane,
             turn,
             entrance,
+            node: 0,
+            geometry: *geometry,
+            route: None,
+            route_leg: 0,
+            next_step: None,
         }
     }
 
-    pub fn update(&mut self, dt: Duration ) {
+    pub fn update(&mut self, dt: Duration, leader: Option<&Leader>, rng: &mut dyn RngCore) {
         //vroom vroom
         //println!("state {:?}", self.state);
         //println!("x {0} y {1} d {2} dt {3}", self.x, self.y, self.direction, dt.as_secs_f64());
+        self.previous_bounds = self.bounds.clone();
+        self.acceleration = self.model.acceleration(self.speed, leader, dt.as_secs_f64(), rng);
+        self.speed = (self.speed + self.acceleration * dt.as_secs_f64()).max(0.0);
+        self.trip.record_step(dt, self.speed, self.is_stopped());
+
+        self.advance(dt);
+    }
+
+    // Moves the vehicle along its lane and turn at its current speed
+    fn advance(&mut self, dt: Duration) {
         match self.state {
             State::Driving => {
-                self.bounds.x += self.speed as f64 * dt.as_secs_f64() * self.direction.cos();
-                self.bounds.y += self.speed as f64 * dt.as_secs_f64() * self.direction.sin();
+                self.bounds.x += self.speed * dt.as_secs_f64() * self.direction.cos();
+                self.bounds.y += self.speed * dt.as_secs_f64() * self.direction.sin();
 
                 self.direction = (self.direction + 2.0 * std::f64::consts::PI) % (2.0 * std::f64::consts::PI);
                 self.bounds.direction = self.direction;
 
-                self.vision = create_vehicle_vision((self.bounds.x, self.bounds.y), self.direction, 20, 10);
+                self.vision = create_vehicle_vision((self.bounds.x, self.bounds.y), self.direction, VISION_LENGTH, self.bounds.height);
 
-                if  self.bounds.x < WIDTH as f64 / 2.0 + 50.0 &&
-                self.bounds.x > WIDTH as f64 / 2.0 - 50.0 &&
-                self.bounds.y < HEIGHT as f64 / 2.0 + 50.0 &&
-                self.bounds.y > HEIGHT as f64 / 2.0 - 50.0 {
+                if self.geometry.in_box(self.bounds.x, self.bounds.y) {
                     self.state = State::Turning;
+                    self.entered_box = true;
                 }
             },
             State::Stop => {
@@ -113,45 +241,114 @@ impl Vehicle {
             State::Turning => {
                 let radius = self.get_turn_radius();
                 self.apply_turn(radius, dt.as_secs_f64());
-                if  self.bounds.x >= WIDTH as f64 / 2.0 + 50.0 ||
-                self.bounds.x <= WIDTH as f64 / 2.0 - 50.0 ||
-                self.bounds.y >= HEIGHT as f64 / 2.0 + 50.0 ||
-                self.bounds.y <= HEIGHT as f64 / 2.0 - 50.0 {
+                if !self.geometry.in_box(self.bounds.x, self.bounds.y) {
                     self.quantize_direction();
                     self.state = State::Driving;
                 }
             },
         }
-        self.speed = 1000;
     }
 
 
 
-    pub fn get_turn_radius(&self) -> f64 {
-        return match self.lane {
-            Lane::Right => {
-                match self.turn {
-                    TurnDirection::Right => 12.5,
-                    TurnDirection::Left => 87.5,
-                    TurnDirection::Straight => 0.0,
-                }
-            }
-            Lane::Left => {
-                match self.turn {
-                    TurnDirection::Right => 37.5,
-                    TurnDirection::Left => 62.5,
-                    TurnDirection::Straight => 0.0,
-                }
+    pub fn trip_record(&self, exited_at: Duration) -> TripRecord {
+        TripRecord {
+            vehicle_id: self.id,
+            node: self.trip.origin_node,
+            entrance: self.trip.origin_entrance,
+            turn: self.trip.origin_turn.unwrap_or(self.turn),
+            spawned_at: self.trip.spawned_at,
+            entered_at: self.trip.entered_at.unwrap_or(self.trip.spawned_at),
+            exited_at,
+            stops: self.trip.stops,
+            stopped_time: self.trip.stopped_time,
+            distance: self.trip.distance,
+            free_flow_time: Duration::from_secs_f64(self.trip.distance / self.model.desired_speed()),
+        }
+    }
+
+    // The part of the trip at the node the vehicle is at, from its spawn or handover there until
+    // exited_at; the node's own entrance, turn and delay
+    pub fn segment_record(&self, exited_at: Duration) -> TripRecord {
+        let start = &self.trip.segment;
+        let (spawned_at, entered_at) = match start.at {
+            Some(at) => (at, at),
+            None => (self.trip.spawned_at, self.trip.entered_at.unwrap_or(self.trip.spawned_at)),
+        };
+        let distance = self.trip.distance - start.distance;
+        TripRecord {
+            vehicle_id: self.id,
+            node: self.node,
+            entrance: self.entrance,
+            turn: self.turn,
+            spawned_at,
+            entered_at,
+            exited_at,
+            stops: self.trip.stops - start.stops,
+            stopped_time: self.trip.stopped_time.saturating_sub(start.stopped_time),
+            distance,
+            free_flow_time: Duration::from_secs_f64(distance / self.model.desired_speed()),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    // Still on its entrance leg, short of the intersection box
+    pub fn is_approaching(&self) -> bool {
+        !self.entered_box
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.speed < STOPPED_SPEED
+    }
+
+    // Leg the vehicle is on after the box, as a quarter-turn heading (0 east, 1 south, 2 west, 3 north)
+    pub fn exit_heading(&self) -> Option<usize> {
+        match self.state {
+            State::Driving if self.entered_box => Some((self.direction / (std::f64::consts::PI / 2.0)).round() as usize % 4),
+            _ => None,
+        }
+    }
+
+    // Centre points a vehicle sweeps inside the intersection box when entering at entrance and making turn
+    pub fn movement_path(geometry: &Geometry, entrance: u32, turn: TurnDirection) -> Vec<(f64, f64)> {
+        let mut vehicle = Self::with_turn(0, 100.0, &VehicleType::default(), geometry, entrance, turn);
+        let step = Duration::from_millis(5);
+        let mut path = Vec::new();
+
+        while !vehicle.check_bounds() {
+            vehicle.advance(step);
+            match vehicle.state {
+                State::Turning => path.push((vehicle.bounds.x, vehicle.bounds.y)),
+                _ if !path.is_empty() => break,
+                _ => (),
             }
         }
+
+        path
+    }
+
+    // Arcs are centred on the box corner on the side of the turn; U-turns on the centre line at the
+    // edge of the box
+    pub fn get_turn_radius(&self) -> f64 {
+        let half_box = self.geometry.box_size / 2.0;
+        let offset = self.geometry.lane_offset(&self.lane);
+        match self.turn {
+            TurnDirection::Right => half_box - offset,
+            TurnDirection::Left => half_box + offset,
+            TurnDirection::Straight => 0.0,
+            TurnDirection::UTurn => offset,
+        }
     }
 
     pub fn apply_turn(&mut self, radius: f64, delta_time: f64) {
-        let angular_velocity = self.speed as f64 / radius; // Angular velocity = speed / radius
+        let angular_velocity = self.speed / radius; // Angular velocity = speed / radius
         let angular_change = angular_velocity * delta_time; // Change in angle is angular velocity * time
 
         match self.turn {
-            TurnDirection::Left => self.direction -= angular_change,
+            TurnDirection::Left | TurnDirection::UTurn => self.direction -= angular_change,
             TurnDirection::Right => self.direction += angular_change,
             TurnDirection::Straight => (),
         }
@@ -160,33 +357,58 @@ impl Vehicle {
         self.direction = (self.direction + 2.0 * std::f64::consts::PI) % (2.0 * std::f64::consts::PI);
 
         // Update the vehicle's position based on the new direction
-        self.bounds.x += self.speed as f64 * delta_time * self.direction.cos();
-        self.bounds.y += self.speed as f64 * delta_time * self.direction.sin();
+        self.bounds.x += self.speed * delta_time * self.direction.cos();
+        self.bounds.y += self.speed * delta_time * self.direction.sin();
         self.bounds.direction = self.direction;
 
-        self.vision = create_vehicle_vision((self.bounds.x, self.bounds.y), self.direction, 20, 10);
+        self.vision = create_vehicle_vision((self.bounds.x, self.bounds.y), self.direction, VISION_LENGTH, self.bounds.height);
     }
 
     fn quantize_direction(&mut self) {
         let pi = std::f64::consts::PI;
         let half_pi = pi / 2.0;
         self.direction = (self.direction / half_pi).round() * half_pi;
-        self.direction = self.direction % (2.0 * pi);
+        self.direction %= 2.0 * pi;
     }
 
 
     pub fn check_bounds(&self) -> bool {
-        if self.bounds.x > WIDTH as f64 || self.bounds.x < 0.0 || self.bounds.y > HEIGHT as f64 || self.bounds.y < 0.0 {
+        !self.geometry.on_map(self.bounds.x, self.bounds.y)
+    }
+
+    // How far the vehicle's centre is short of the box of the node it is at, along its heading
+    pub fn distance_to_box(&self) -> f64 {
+        let (center_x, center_y) = self.geometry.center();
+        self.distance_ahead(center_x, center_y) - self.geometry.box_size / 2.0
+    }
+
+    // Signed distance from the vehicle's centre to a point, measured along its heading
+    pub fn distance_ahead(&self, x: f64, y: f64) -> f64 {
+        (x - self.bounds.x) * self.direction.cos() + (y - self.bounds.y) * self.direction.sin()
+    }
 
-            return true;
+    // alpha is how far the renderer is between the previous physics step and the current one
+    pub fn interpolated_bounds(&self, alpha: f64) -> Rectangle {
+        let pi = std::f64::consts::PI;
+        let mut turn = self.bounds.direction - self.previous_bounds.direction;
+        if turn > pi {
+            turn -= 2.0 * pi;
+        } else if turn < -pi {
+            turn += 2.0 * pi;
         }
 
-        return false;
+        Rectangle::new(
+            self.previous_bounds.x + (self.bounds.x - self.previous_bounds.x) * alpha,
+            self.previous_bounds.y + (self.bounds.y - self.previous_bounds.y) * alpha,
+            self.bounds.width,
+            self.bounds.height,
+            self.previous_bounds.direction + turn * alpha,
+        )
     }
 
-    pub fn draw(&self, frame: &mut [u8], frame_width: u32, frame_height: u32) {
+    pub fn draw(&self, frame: &mut [u8], frame_width: u32, frame_height: u32, alpha: f64) {
 
-        draw_rectangle(frame, frame_width, frame_height, &self.bounds, [0,0,255,255], true);
+        draw_rectangle(frame, frame_width, frame_height, &self.interpolated_bounds(alpha), [0,0,255,255], true);
         //self.draw_rectangle(frame, frame_width, frame_height, &self.vision, [0,255,0,255], false);
     }
 